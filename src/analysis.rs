@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::fmt;
+use super::regions::RegionTree;
+
+/// How a periodic pattern repeats itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Periodicity {
+    /// Number of generations after which the pattern repeats
+    pub period: usize,
+    /// Horizontal displacement of the pattern over one period
+    pub dx: i64,
+    /// Vertical displacement of the pattern over one period
+    pub dy: i64,
+    /// First generation (relative to the start of the analysis) of the repeating cycle
+    pub start: usize,
+}
+
+/// What a pattern turned out to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classification {
+    /// The pattern has no cells
+    Empty,
+    /// The pattern repeats itself in place
+    Oscillator(Periodicity),
+    /// The pattern repeats itself, translated
+    Spaceship(Periodicity),
+    /// Nothing repeated within the given number of generations
+    Aperiodic {
+        /// Number of generations the pattern was run for
+        generations: usize,
+    },
+}
+
+impl Periodicity {
+    /// Returns the speed of the pattern as a fraction of c (one cell per generation),
+    /// as `(numerator, denominator)` in lowest terms
+    pub fn speed(&self) -> (usize, usize) {
+        let distance = self.dx.unsigned_abs().max(self.dy.unsigned_abs()) as usize;
+        let divisor = gcd(distance, self.period).max(1);
+        (distance / divisor, self.period / divisor)
+    }
+
+    /// Returns the direction of travel: "orthogonal", "diagonal" or "oblique"
+    pub fn direction(&self) -> &'static str {
+        if self.dx == 0 || self.dy == 0 {
+            "orthogonal"
+        } else if self.dx.abs() == self.dy.abs() {
+            "diagonal"
+        } else {
+            "oblique"
+        }
+    }
+}
+
+impl fmt::Display for Periodicity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.dx == 0 && self.dy == 0 {
+            return write!(f, "p{}", self.period)
+        }
+
+        match self.speed() {
+            (1, denominator) => write!(f, "c/{}", denominator)?,
+            (numerator, denominator) => write!(f, "{}c/{}", numerator, denominator)?,
+        }
+        write!(f, " {} ({}, {})/{}", self.direction(), self.dx, self.dy, self.period)
+    }
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Classification::Empty => write!(f, "empty"),
+            Classification::Oscillator(periodicity) => write!(f, "oscillator {}", periodicity),
+            Classification::Spaceship(periodicity) => write!(f, "spaceship {}", periodicity),
+            Classification::Aperiodic { generations } => {
+                write!(f, "no period found within {} generations", generations)
+            }
+        }
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Translates `cells` so that they are relative to the top-left corner of their bounding box, and sorts them.
+/// Returns the translated cells, and the translation that was applied.
+///
+/// The dynamics of the partition are only invariant under translations by an even vector, or by `(1, 1)` if the phase is swapped as well.
+/// The corner is thus rounded so that it has the same parity as `phase` in both coordinates if `phase_symmetric` is true,
+/// and so that it is even otherwise.
+pub fn normalize(cells: &[(i64, i64)], phase: usize, phase_symmetric: bool) -> (Vec<(i64, i64)>, (i64, i64)) {
+    let min_x = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+    let min_y = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+    let parity = if phase_symmetric { phase as i64 % 2 } else { 0 };
+    let origin = (
+        (min_x - parity).div_euclid(2) * 2 + parity,
+        (min_y - parity).div_euclid(2) * 2 + parity,
+    );
+
+    let mut res = cells.iter().map(|&(x, y)| (x - origin.0, y - origin.1)).collect::<Vec<_>>();
+    res.sort_unstable();
+    (res, origin)
+}
+
+/// Runs a copy of `tree` for at most `max_generations` generations, until its state repeats (up to a translation),
+/// and tells whether it is an oscillator or a spaceship.
+pub fn classify(tree: &RegionTree, max_generations: usize) -> Classification {
+    let mut tree = tree.clone();
+    let phase_symmetric = tree.rule.is_phase_symmetric();
+    // (phase, normalized cells) -> (generation, origin)
+    let mut seen: HashMap<_, (usize, (i64, i64))> = HashMap::new();
+
+    for generation in 0..=max_generations {
+        let cells = tree.particles().map(|(_, pos, _)| pos).collect::<Vec<_>>();
+        if cells.is_empty() {
+            return Classification::Empty
+        }
+
+        let phase = tree.step % 2;
+        let (cells, origin) = normalize(&cells, phase, phase_symmetric);
+        let key = (if phase_symmetric { 0 } else { phase }, cells);
+        if let Some(&(start, previous_origin)) = seen.get(&key) {
+            let periodicity = Periodicity {
+                period: generation - start,
+                dx: origin.0 - previous_origin.0,
+                dy: origin.1 - previous_origin.1,
+                start,
+            };
+            return if periodicity.dx == 0 && periodicity.dy == 0 {
+                Classification::Oscillator(periodicity)
+            } else {
+                Classification::Spaceship(periodicity)
+            }
+        }
+        seen.insert(key, (generation, origin));
+
+        if generation < max_generations {
+            tree.tick();
+        }
+    }
+
+    Classification::Aperiodic { generations: max_generations }
+}
+
+#[test]
+fn test_classify() {
+    // A lone particle oscillates with period 4
+    let mut tree = RegionTree::new();
+    tree.insert(0, 0, 0);
+    assert_eq!(
+        classify(&tree, 100),
+        Classification::Oscillator(Periodicity { period: 4, dx: 0, dy: 0, start: 0 })
+    );
+
+    // The lightest spaceship
+    let mut tree = RegionTree::new();
+    for &(x, y) in &[(0, 0), (1, 0), (0, 2), (1, 2)] {
+        tree.insert(x, y, 0);
+    }
+    let periodicity = Periodicity { period: 12, dx: -2, dy: 0, start: 0 };
+    assert_eq!(classify(&tree, 1000), Classification::Spaceship(periodicity));
+    assert_eq!(periodicity.speed(), (1, 6));
+    assert_eq!(periodicity.direction(), "orthogonal");
+
+    assert_eq!(classify(&RegionTree::new(), 10), Classification::Empty);
+    assert_eq!(classify(&tree, 2), Classification::Aperiodic { generations: 2 });
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use super::analysis::{classify, Classification};
+use super::census::census;
+use super::packed::PackedTree;
+use super::regions::RegionTree;
+use super::snapshot::Checkpointer;
+
+/// A condition on which a batch run stops early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopCondition {
+    /// The whole pattern is an oscillator or a spaceship
+    Periodic,
+    /// Every object of the pattern could be identified, as in a soup search
+    Settled,
+}
+
+impl StopCondition {
+    /// Returns true if the condition holds for `tree`, running objects for at most `max_period` generations to identify them
+    pub fn holds(&self, tree: &RegionTree, max_period: usize) -> bool {
+        match self {
+            StopCondition::Periodic => !matches!(classify(tree, max_period), Classification::Aperiodic { .. }),
+            StopCondition::Settled => census(tree, max_period).n_unclassified() == 0,
+        }
+    }
+}
+
+impl fmt::Display for StopCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopCondition::Periodic => write!(f, "periodic"),
+            StopCondition::Settled => write!(f, "settled"),
+        }
+    }
+}
+
+impl FromStr for StopCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "periodic" => Ok(StopCondition::Periodic),
+            "settled" => Ok(StopCondition::Settled),
+            _ => Err(format!("Unknown stop condition: {:?}", s)),
+        }
+    }
+}
+
+/// The engine stepping the simulation during a batch run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// `RegionTree`, which keeps track of each particle
+    #[default]
+    Regions,
+    /// `PackedTree`, which is faster but only knows which cells are alive: particles lose their ids and colors.
+    /// It only simulates the unbounded plane, so bounded universes are still run with `RegionTree`
+    Packed,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Engine::Regions => write!(f, "regions"),
+            Engine::Packed => write!(f, "packed"),
+        }
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "regions" => Ok(Engine::Regions),
+            "packed" => Ok(Engine::Packed),
+            _ => Err(format!("Unknown engine: {:?}", s)),
+        }
+    }
+}
+
+/// Parameters of a batch run
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// Generation to stop at; without it, the run only stops once `stop_when` holds
+    pub until: Option<usize>,
+    /// Condition to stop at, checked every `check_interval` generations
+    pub stop_when: Option<StopCondition>,
+    /// Number of generations between two checks of `stop_when`
+    pub check_interval: usize,
+    /// Maximum number of generations to run each object for when checking `stop_when`
+    pub max_period: usize,
+    /// Engine the universe is run with
+    pub engine: Engine,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            until: None,
+            stop_when: None,
+            check_interval: 100,
+            max_period: 1000,
+            engine: Engine::Regions,
+        }
+    }
+}
+
+/// Why a batch run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOutcome {
+    /// The generation `until` was reached, before `stop_when` held if there is one
+    Reached,
+    /// `stop_when` held
+    Stopped(StopCondition),
+}
+
+impl fmt::Display for BatchOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BatchOutcome::Reached => write!(f, "reached"),
+            BatchOutcome::Stopped(condition) => write!(f, "stopped: {}", condition),
+        }
+    }
+}
+
+/// Runs `tree` as fast as possible until `config.until`, or until `config.stop_when` holds.
+/// The condition is checked before the first generation and then every `check_interval` generations,
+/// and snapshots are saved along the way if there is a `checkpointer`.
+/// With `Engine::Packed`, the universe is only converted back into a `RegionTree` to check the condition, to save snapshots and once it stops.
+/// Without `until` nor `stop_when`, returns immediately
+pub fn run(tree: &mut RegionTree, config: &BatchConfig, mut checkpointer: Option<&mut Checkpointer>) -> io::Result<BatchOutcome> {
+    let check_interval = config.check_interval.max(1);
+    let until = match (config.until, config.stop_when) {
+        (Some(until), _) => until,
+        (None, Some(_)) => usize::MAX,
+        (None, None) => tree.step,
+    };
+    if config.engine == Engine::Packed && tree.topology().size().is_none() {
+        return run_packed(tree, config, until, checkpointer)
+    }
+
+    loop {
+        if let Some(condition) = config.stop_when {
+            if condition.holds(tree, config.max_period) {
+                return Ok(BatchOutcome::Stopped(condition))
+            }
+        }
+        if tree.step >= until {
+            return Ok(BatchOutcome::Reached)
+        }
+
+        let next_check = until.min(tree.step.saturating_add(check_interval));
+        while tree.step < next_check {
+            tree.tick();
+            if let Some(checkpointer) = &mut checkpointer {
+                checkpointer.update(tree)?;
+            }
+        }
+    }
+}
+
+/// Same as `run`, stepping a `PackedTree` which stays packed until the condition has to be checked or a snapshot has to be saved
+fn run_packed(tree: &mut RegionTree, config: &BatchConfig, until: usize, mut checkpointer: Option<&mut Checkpointer>) -> io::Result<BatchOutcome> {
+    let check_interval = config.check_interval.max(1);
+    let threads = tree.threads;
+    let mut universe = PackedTree::from_tree(tree);
+    let unpack = |universe: &PackedTree| {
+        let mut tree = universe.to_tree();
+        tree.threads = threads;
+        tree
+    };
+
+    let outcome = loop {
+        if let Some(condition) = config.stop_when {
+            *tree = unpack(&universe);
+            if condition.holds(tree, config.max_period) {
+                break BatchOutcome::Stopped(condition)
+            }
+        }
+        if universe.step >= until {
+            break BatchOutcome::Reached
+        }
+
+        let mut next_stop = until;
+        if config.stop_when.is_some() {
+            next_stop = next_stop.min(universe.step.saturating_add(check_interval));
+        }
+        if let Some(checkpointer) = &checkpointer {
+            next_stop = next_stop.min(checkpointer.next_step());
+        }
+        while universe.step < next_stop {
+            universe.tick();
+        }
+        if let Some(checkpointer) = &mut checkpointer {
+            if universe.step == checkpointer.next_step() {
+                checkpointer.update(&unpack(&universe))?;
+            }
+        }
+    };
+
+    // With a condition, `tree` was already unpacked to check it on the last generation
+    if config.stop_when.is_none() {
+        *tree = unpack(&universe);
+    }
+    Ok(outcome)
+}
+
+#[test]
+fn test_batch_run() {
+    let mut tree = RegionTree::new();
+    tree.insert(0, 0, 0);
+    tree.insert(0, 1, 0);
+    tree.insert(0, 3, 0);
+    tree.insert(0, 4, 0);
+
+    let config = BatchConfig {
+        until: Some(250),
+        ..BatchConfig::default()
+    };
+    assert_eq!(run(&mut tree, &config, None).unwrap(), BatchOutcome::Reached);
+    assert_eq!(tree.step, 250);
+
+    // The lightest ship is periodic from the start, which is checked before running it
+    let config = BatchConfig {
+        until: Some(1000),
+        stop_when: Some(StopCondition::Periodic),
+        ..BatchConfig::default()
+    };
+    assert_eq!(run(&mut tree, &config, None).unwrap(), BatchOutcome::Stopped(StopCondition::Periodic));
+    assert_eq!(tree.step, 250);
+
+    // With its mirror image moving away from it, the pattern as a whole never repeats
+    let mut pair = tree.clone();
+    for (_, (x, y), _) in tree.particles().collect::<Vec<_>>() {
+        pair.insert(81 - x, y, 0);
+    }
+    let config = BatchConfig {
+        until: Some(1000),
+        stop_when: Some(StopCondition::Periodic),
+        check_interval: 300,
+        ..BatchConfig::default()
+    };
+    assert_eq!(run(&mut pair, &config, None).unwrap(), BatchOutcome::Reached);
+    assert_eq!(pair.step, 1000);
+    assert_eq!(pair.population(), 8);
+
+    // Both engines agree on where the cells end up
+    let mut packed = tree.clone();
+    let config = BatchConfig {
+        until: Some(1234),
+        engine: Engine::Packed,
+        ..BatchConfig::default()
+    };
+    assert_eq!(run(&mut packed, &config, None).unwrap(), BatchOutcome::Reached);
+    run(&mut tree, &BatchConfig { engine: Engine::Regions, ..config }, None).unwrap();
+    assert_eq!(packed.step, 1234);
+    let mut cells = packed.particles().map(|(_, position, _)| position).collect::<Vec<_>>();
+    let mut expected = tree.particles().map(|(_, position, _)| position).collect::<Vec<_>>();
+    cells.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(cells, expected);
+
+    // The packed engine checks the condition on the same generations
+    let config = BatchConfig {
+        until: Some(2000),
+        stop_when: Some(StopCondition::Periodic),
+        check_interval: 300,
+        engine: Engine::Packed,
+        ..BatchConfig::default()
+    };
+    let mut packed = pair.clone();
+    assert_eq!(run(&mut packed, &config, None).unwrap(), BatchOutcome::Reached);
+    assert_eq!(packed.step, 2000);
+    assert_eq!(packed.population(), 8);
+}
@@ -1,5 +1,14 @@
-use super::{nearest_region, RegionTree, REGION_SIZE, NEIGHBORS};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use super::regions::{nearest_region, RegionTree, REGION_SIZE, NEIGHBORS};
+use super::analysis::{classify, normalize, Classification, Periodicity};
+use super::rules::BlockRule;
 
+/// Default maximum distance between two cells of the same object, as used by `get_objects`
+pub const OBJECT_SEPARATION: i64 = 2;
+
+/// Returns the non-empty regions connected to the region containing `x`, `y`, as the coordinates of their top-left corner.
+/// Stops looking for more regions once `max_size` regions were found, unless it is 0.
 pub fn get_island(tree: &RegionTree, x: i64, y: i64, max_size: usize) -> Vec<(i64, i64)> {
     let (x, y) = nearest_region(x, y);
     const R: i64 = REGION_SIZE as i64;
@@ -41,7 +50,8 @@ fn test_get_island() {
     assert_eq!(get_island(&tree, 0, 0, 1), vec![(0, 0)]);
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// A rectangle of cells, cut out of a universe
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ship {
     contents: Vec<bool>,
     width: usize,
@@ -50,6 +60,7 @@ pub struct Ship {
     y: i64,
 }
 
+/// Returns the cells of the island of regions containing `x`, `y`, as found by `get_island`
 pub fn get_ship(tree: &RegionTree, x: i64, y: i64, max_size: usize) -> Option<Ship> {
     let regions = get_island(tree, x, y, max_size).into_iter().map(|i| &tree.regions[tree.hashmap[&i]]).collect::<Vec<_>>();
     let mut min_x = x;
@@ -57,7 +68,7 @@ pub fn get_ship(tree: &RegionTree, x: i64, y: i64, max_size: usize) -> Option<Sh
     let mut min_y = y;
     let mut max_y = y;
 
-    if regions.len() == 0 {
+    if regions.is_empty() {
         return None
     }
 
@@ -94,8 +105,6 @@ pub fn get_ship(tree: &RegionTree, x: i64, y: i64, max_size: usize) -> Option<Sh
         }
     }
 
-    println!("{:?}", contents);
-
     Some(Ship {
         contents,
         width,
@@ -105,6 +114,317 @@ pub fn get_ship(tree: &RegionTree, x: i64, y: i64, max_size: usize) -> Option<Sh
     })
 }
 
+impl Ship {
+    /// Creates a ship from the coordinates of its cells
+    pub fn from_cells(cells: &[(i64, i64)]) -> Option<Self> {
+        let min_x = cells.iter().map(|&(x, _)| x).min()?;
+        let max_x = cells.iter().map(|&(x, _)| x).max()?;
+        let min_y = cells.iter().map(|&(_, y)| y).min()?;
+        let max_y = cells.iter().map(|&(_, y)| y).max()?;
+
+        let width = (max_x - min_x + 1) as usize;
+        let height = (max_y - min_y + 1) as usize;
+        let mut contents = vec![false; width * height];
+        for &(x, y) in cells {
+            contents[(x - min_x) as usize + (y - min_y) as usize * width] = true;
+        }
+
+        Some(Self {
+            contents,
+            width,
+            height,
+            x: min_x,
+            y: min_y,
+        })
+    }
+
+    /// Returns the width of the bounding box of the ship
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the bounding box of the ship
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Coordinates of the top-left corner of the ship
+    pub fn position(&self) -> (i64, i64) {
+        (self.x, self.y)
+    }
+
+    /// Returns the number of cells of the ship
+    pub fn population(&self) -> usize {
+        self.contents.iter().filter(|&&cell| cell).count()
+    }
+
+    /// Returns the coordinates of the cells of the ship
+    pub fn cells(&self) -> Vec<(i64, i64)> {
+        self.contents
+            .iter()
+            .enumerate()
+            .filter(|(_, &cell)| cell)
+            .map(|(i, _)| (self.x + (i % self.width) as i64, self.y + (i / self.width) as i64))
+            .collect()
+    }
+
+    /// Creates a new `RegionTree` containing only this ship, at its original position, following `rule` and starting at `step`
+    pub fn to_tree(&self, rule: &BlockRule, step: usize) -> RegionTree {
+        let mut tree = RegionTree::with_rule(rule.clone());
+        for (x, y) in self.cells() {
+            tree.insert(x, y, 0);
+        }
+        tree.step = step;
+        tree
+    }
+
+    /// Returns the canonical code of this ship, following `rule` and starting at `step`,
+    /// or `None` if it doesn't repeat itself within `max_generations`
+    pub fn code(&self, rule: &BlockRule, step: usize, max_generations: usize) -> Option<String> {
+        let tree = self.to_tree(rule, step);
+        match classify(&tree, max_generations) {
+            Classification::Oscillator(periodicity) | Classification::Spaceship(periodicity) => {
+                Some(encode(&tree, &periodicity))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Digits of a pattern code, each encoding 5 cells of a row: bit `i` of a digit is the cell in column `5 * n + i`,
+/// where `n` is the position of the digit within the row
+const CODE_DIGITS: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+
+/// Errors that can arise when decoding a pattern code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeError {
+    /// The code doesn't start with `xs`, `xp` or `xq`, followed by a number
+    Prefix(String),
+    /// A row contains a character which isn't a digit
+    Digit(char),
+    /// The code has too many or too few parts
+    Parts(usize),
+    /// The last part of the code isn't the phase `1`
+    Phase(String),
+}
+
+impl fmt::Display for CodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodeError::Prefix(prefix) => write!(f, "Invalid pattern code prefix: {:?}", prefix),
+            CodeError::Digit(c) => write!(f, "Invalid digit in pattern code: {:?}", c),
+            CodeError::Parts(n) => write!(f, "Expected 2 or 3 parts in pattern code, got {}", n),
+            CodeError::Phase(phase) => write!(f, "Invalid phase in pattern code: {:?}, expected \"1\"", phase),
+        }
+    }
+}
+
+impl std::error::Error for CodeError {}
+
+/// Encodes cells with non-negative coordinates as rows of digits, separated by `-`
+fn encode_cells(cells: &[(i64, i64)]) -> String {
+    let height = cells.iter().map(|&(_, y)| y + 1).max().unwrap_or(0) as usize;
+    let width = cells.iter().map(|&(x, _)| x + 1).max().unwrap_or(0) as usize;
+    let mut rows = vec![vec![0u8; width.div_ceil(5)]; height];
+    for &(x, y) in cells {
+        rows[y as usize][x as usize / 5] |= 1 << (x % 5);
+    }
+
+    rows.into_iter()
+        .map(|row| {
+            let length = row.iter().rposition(|&digit| digit != 0).map(|n| n + 1).unwrap_or(1);
+            row[..length].iter().map(|&digit| CODE_DIGITS[digit as usize] as char).collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Decodes the cells written by `encode_cells`
+fn decode_cells(body: &str) -> Result<Vec<(i64, i64)>, CodeError> {
+    let mut res = Vec::new();
+    for (y, row) in body.split('-').enumerate() {
+        for (n, c) in row.chars().enumerate() {
+            let digit = CODE_DIGITS.iter().position(|&d| d as char == c).ok_or(CodeError::Digit(c))?;
+            for bit in 0..5 {
+                if (digit >> bit) & 1 == 1 {
+                    res.push(((5 * n + bit) as i64, y as i64));
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Returns the canonical code of a periodic pattern, as found by `analysis::classify`.
+///
+/// The code is made of a prefix (`xs` followed by the population for still patterns, `xp` followed by the period for oscillators
+/// and `xq` followed by the period for spaceships) and of the cells of the pattern, as rows of base-32 digits.
+/// Among every generation of the cycle and every symmetry of the rule, the shortest and then lexicographically smallest
+/// encoding of the cells is picked, so that the same object always gets the same code.
+/// Rules whose phases differ get a `_1` suffix if that encoding was found on an odd phase.
+pub fn encode(tree: &RegionTree, periodicity: &Periodicity) -> String {
+    let mut tree = tree.clone();
+    for _ in 0..periodicity.start {
+        tree.tick();
+    }
+
+    let phase_symmetric = tree.rule.is_phase_symmetric();
+    let symmetries = tree.rule.symmetries();
+    let mut best: Option<(usize, String, usize)> = None;
+    for _ in 0..periodicity.period {
+        let cells = tree.particles().map(|(_, pos, _)| pos).collect::<Vec<_>>();
+        let phase = tree.step % 2;
+        for symmetry in &symmetries {
+            let transformed = cells.iter().map(|&(x, y)| symmetry.apply(x, y)).collect::<Vec<_>>();
+            let (normalized, _) = normalize(&transformed, phase, phase_symmetric);
+            let body = encode_cells(&normalized);
+            let candidate = (body.len(), body, if phase_symmetric { 0 } else { phase });
+            if best.as_ref().map(|best| candidate < *best).unwrap_or(true) {
+                best = Some(candidate);
+            }
+        }
+        tree.tick();
+    }
+
+    let prefix = if periodicity.dx != 0 || periodicity.dy != 0 {
+        format!("xq{}", periodicity.period)
+    } else if periodicity.period == 1 {
+        format!("xs{}", tree.population())
+    } else {
+        format!("xp{}", periodicity.period)
+    };
+    let (_, body, phase) = best.unwrap_or_default();
+    if phase == 1 {
+        format!("{}_{}_1", prefix, body)
+    } else {
+        format!("{}_{}", prefix, body)
+    }
+}
+
+/// Creates a new `RegionTree` following `rule`, containing the pattern described by `code`, as returned by `encode`
+pub fn decode(code: &str, rule: BlockRule) -> Result<RegionTree, CodeError> {
+    let parts = code.split('_').collect::<Vec<_>>();
+    if parts.len() < 2 || parts.len() > 3 {
+        return Err(CodeError::Parts(parts.len()))
+    }
+
+    let prefix = parts[0];
+    let valid_prefix = ["xs", "xp", "xq"].iter().any(|kind| prefix.starts_with(kind))
+        && prefix.len() > 2
+        && prefix[2..].chars().all(|c| c.is_ascii_digit());
+    if !valid_prefix {
+        return Err(CodeError::Prefix(prefix.to_string()))
+    }
+
+    let mut tree = RegionTree::with_rule(rule);
+    for (x, y) in decode_cells(parts[1])? {
+        tree.insert(x, y, 0);
+    }
+    match parts.get(2) {
+        None => {}
+        Some(&"1") => tree.step = 1,
+        Some(phase) => return Err(CodeError::Phase(phase.to_string())),
+    }
+    Ok(tree)
+}
+
+/// Splits the universe into separate objects: two cells belong to the same object if they are
+/// at most `separation` cells apart horizontally and vertically, or if they are linked by a chain of such cells
+pub fn get_objects(tree: &RegionTree, separation: i64) -> Vec<Ship> {
+    let mut res = Vec::new();
+    let mut visited_regions = HashSet::new();
+
+    for region in tree.regions.iter().filter(|region| region.n_cells > 0) {
+        if visited_regions.contains(&(region.x, region.y)) {
+            continue
+        }
+
+        // Objects can't span over more than an island of regions, since the separation is smaller than a region
+        let island = get_island(tree, region.x, region.y, 0);
+        let mut cells = HashMap::new();
+        for &(rx, ry) in &island {
+            visited_regions.insert((rx, ry));
+            let region = &tree.regions[tree.hashmap[&(rx, ry)]];
+            for (y, row) in region.cells.iter().enumerate() {
+                for (x, &cell) in row.iter().enumerate() {
+                    if cell > 0 {
+                        cells.insert((rx + x as i64, ry + y as i64), false);
+                    }
+                }
+            }
+        }
+
+        // Flood-fill the cells of the island
+        let mut starts = cells.keys().copied().collect::<Vec<_>>();
+        starts.sort_unstable();
+        for start in starts {
+            if cells[&start] {
+                continue
+            }
+            let mut object = Vec::new();
+            let mut stack = vec![start];
+            cells.insert(start, true);
+            while let Some((x, y)) = stack.pop() {
+                object.push((x, y));
+                for dy in -separation..=separation {
+                    for dx in -separation..=separation {
+                        if let Some(visited) = cells.get_mut(&(x + dx, y + dy)) {
+                            if !*visited {
+                                *visited = true;
+                                stack.push((x + dx, y + dy));
+                            }
+                        }
+                    }
+                }
+            }
+            res.extend(Ship::from_cells(&object));
+        }
+    }
+
+    res
+}
+
+#[test]
+fn test_get_objects() {
+    let mut tree = RegionTree::new();
+    for &(x, y) in &[(0, 0), (2, 0), (2, 2), (20, 0), (40, 40), (41, 41), (15, 15), (17, 16)] {
+        tree.insert(x, y, 0);
+    }
+    let mut objects = get_objects(&tree, OBJECT_SEPARATION);
+    objects.sort_by_key(|ship| ship.position());
+    assert_eq!(
+        objects.iter().map(|ship| (ship.position(), ship.population())).collect::<Vec<_>>(),
+        vec![((0, 0), 3), ((15, 15), 2), ((20, 0), 1), ((40, 40), 2)]
+    );
+}
+
+#[test]
+fn test_pattern_code() {
+    let rule = BlockRule::default();
+    let ship = Ship::from_cells(&[(0, 0), (1, 0), (0, 2), (1, 2)]).unwrap();
+    let code = ship.code(&rule, 0, 1000).unwrap();
+    assert!(code.starts_with("xq"));
+
+    // Translating, rotating or advancing the ship doesn't change its code
+    let rotated = Ship::from_cells(&[(8, 4), (8, 5), (6, 4), (6, 5)]).unwrap();
+    assert_eq!(rotated.code(&rule, 0, 1000), Some(code.clone()));
+    let mut tree = ship.to_tree(&rule, 0);
+    for _ in 0..5 {
+        tree.tick();
+    }
+    let cells = tree.particles().map(|(_, pos, _)| pos).collect::<Vec<_>>();
+    assert_eq!(Ship::from_cells(&cells).unwrap().code(&rule, tree.step, 1000), Some(code.clone()));
+
+    let decoded = decode(&code, rule.clone()).unwrap();
+    let cells = decoded.particles().map(|(_, pos, _)| pos).collect::<Vec<_>>();
+    assert_eq!(Ship::from_cells(&cells).unwrap().code(&rule, decoded.step, 1000), Some(code));
+
+    assert_eq!(decode("xp4_1", rule.clone()).unwrap().population(), 1);
+    assert_eq!(decode("yp4_1", rule.clone()).err(), Some(CodeError::Prefix(String::from("yp4"))));
+    assert_eq!(decode("xp4_w", rule.clone()).err(), Some(CodeError::Digit('w')));
+    assert_eq!(decode("xp4_1_2", rule).err(), Some(CodeError::Phase(String::from("2"))));
+}
+
 #[test]
 fn test_get_ship() {
     let mut tree = RegionTree::new();
@@ -121,14 +441,11 @@ fn test_get_ship() {
     tree.insert(REGION_SIZE as i64, 0, 0);
 
     {
-        let mut contents = Vec::new();
-        contents.push(true);
-        for _ in 1..REGION_SIZE {
-            contents.push(false);
-        }
-        contents.push(true);
+        let mut contents = vec![false; REGION_SIZE + 1];
+        contents[0] = true;
+        contents[REGION_SIZE] = true;
         assert_eq!(get_ship(&tree, 0, 0, 0), Some(Ship {
-            contents: contents,
+            contents,
             width: 1 + REGION_SIZE,
             height: 1,
             x: 0,
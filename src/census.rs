@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use super::analysis::{classify, Classification};
+use super::capture::{encode, get_objects, Ship, OBJECT_SEPARATION};
+use super::regions::RegionTree;
+
+/// An object found by `census`, along with what it was identified as
+#[derive(Debug, Clone)]
+pub struct CensusObject {
+    /// Cells of the object, as they were found
+    pub ship: Ship,
+    /// What the object turned out to be
+    pub classification: Classification,
+    /// Canonical code of the object, or `None` if it couldn't be classified
+    pub code: Option<String>,
+}
+
+/// The objects found in a universe, grouped by kind
+#[derive(Debug, Clone, Default)]
+pub struct Census {
+    /// Number of objects of each kind, indexed by their code
+    pub counts: BTreeMap<String, usize>,
+    /// Description of each kind of object, indexed by their code
+    pub descriptions: BTreeMap<String, String>,
+    /// Every object found, in the order they were found in
+    pub objects: Vec<CensusObject>,
+}
+
+impl Census {
+    /// Returns the objects that couldn't be classified within the generation limit
+    pub fn unclassified(&self) -> impl Iterator<Item = &CensusObject> {
+        self.objects
+            .iter()
+            .filter(|object| object.code.is_none())
+    }
+
+    /// Returns the number of objects that couldn't be classified
+    pub fn n_unclassified(&self) -> usize {
+        self.unclassified().count()
+    }
+
+    /// Returns the kinds of objects and their count, the most common first
+    pub fn table(&self) -> Vec<(&str, usize)> {
+        let mut res = self.counts.iter().map(|(code, &count)| (code.as_str(), count)).collect::<Vec<_>>();
+        res.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        res
+    }
+}
+
+impl fmt::Display for Census {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (code, count) in self.table() {
+            writeln!(f, "{}\t{}\t{}", count, code, self.descriptions[code])?;
+        }
+        for object in self.unclassified() {
+            let (x, y) = object.ship.position();
+            writeln!(
+                f,
+                "unclassified object of {} cells at ({}, {})",
+                object.ship.population(),
+                x,
+                y
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits `tree` into separate objects and identifies each of them, by running it on its own for at most `max_generations`
+pub fn census(tree: &RegionTree, max_generations: usize) -> Census {
+    let mut res = Census::default();
+
+    for ship in get_objects(tree, OBJECT_SEPARATION) {
+        let object = ship.to_tree(&tree.rule, tree.step);
+        let classification = classify(&object, max_generations);
+        let code = match classification {
+            Classification::Oscillator(periodicity) | Classification::Spaceship(periodicity) => {
+                Some(encode(&object, &periodicity))
+            }
+            _ => None,
+        };
+        if let Some(code) = &code {
+            *res.counts.entry(code.clone()).or_insert(0) += 1;
+            res.descriptions.entry(code.clone()).or_insert_with(|| classification.to_string());
+        }
+        res.objects.push(CensusObject {
+            ship,
+            classification,
+            code,
+        });
+    }
+
+    res
+}
+
+#[test]
+fn test_census() {
+    let mut tree = RegionTree::new();
+    // Two lone particles and a spaceship
+    for &(x, y) in &[(0, 0), (20, 20), (40, 0), (41, 0), (40, 2), (41, 2)] {
+        tree.insert(x, y, 0);
+    }
+    let census = census(&tree, 1000);
+    assert_eq!(census.objects.len(), 3);
+    assert_eq!(census.n_unclassified(), 0);
+    assert_eq!(census.table(), vec![("xp4_1", 2), ("xq12_4-m", 1)]);
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// The subcommands of the binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Runs a pattern, then writes the final pattern
+    Run,
+    /// Displays a pattern in the terminal or in a window
+    View,
+    /// Renders a pattern into an image
+    Render,
+    /// Identifies and counts the objects of a pattern
+    Census,
+    /// Finds the period and velocity of a pattern
+    Analyse,
+    /// Runs random soups
+    Search,
+    /// Collides two ships
+    Collide,
+    /// Converts a pattern between formats
+    Convert,
+    /// Prints the usage
+    Help,
+}
+
+/// Name, arguments and description of each subcommand
+const COMMANDS: [(Command, &str, &str, &str); 9] = [
+    (Command::Run, "run", "[FILE]", "Runs a pattern as fast as possible, then writes the final pattern"),
+    (Command::View, "view", "[FILE]", "Displays a pattern in the terminal, or in a window with -g"),
+    (Command::Render, "render", "[FILE]", "Renders a pattern into a PNG, GIF or SVG image, without a window"),
+    (Command::Census, "census", "[FILE]", "Runs a pattern, then identifies and counts its objects"),
+    (Command::Analyse, "analyse", "[FILE]", "Tells whether a pattern is an oscillator or a spaceship"),
+    (Command::Search, "search", "[SOUPS]", "Runs random soups and counts the objects they leave"),
+    (Command::Collide, "collide", "CODE CODE", "Collides two ships, given by their codes, in every possible way"),
+    (Command::Convert, "convert", "[FILE] [OUTPUT]", "Converts a pattern between formats"),
+    (Command::Help, "help", "", "Prints this message"),
+];
+
+/// A command-line option: long name, short name, value name (if it takes one) and description
+type Flag = (&'static str, Option<&'static str>, Option<&'static str>, &'static str);
+
+const FLAGS: [Flag; 38] = [
+    ("--standard", None, None, "Read RLE files as standard RLE, where x and y are the size of the pattern"),
+    ("--resume", None, Some("DIR"), "Continue from the latest snapshot in DIR instead of reading a pattern"),
+    ("--jump", None, Some("N"), "Skip N generations ahead with the HashLife engine; particle colors are lost"),
+    ("--threads", Some("-j"), Some("N"), "Number of threads to step the simulation on"),
+    ("--rule", None, Some("RULE"), "Overrides the rule of the pattern"),
+    ("--generations", Some("-n"), Some("N"), "Number of generations to run; overrides the target recorded by --resume"),
+    ("--until", None, Some("GEN"), "Generation to run until, instead of a number of generations; overrides the target recorded by --resume"),
+    ("--engine", None, Some("NAME"), "regions (default), or packed, which is faster but loses the ids and colors of particles"),
+    ("--checkpoint", None, Some("DIR"), "Save snapshots of the universe into DIR while it runs"),
+    ("--checkpoint-interval", None, Some("N"), "Number of generations between two snapshots (default 100000)"),
+    ("--window", Some("-g"), None, "Display the simulation in a window instead of the terminal"),
+    ("--steps", Some("-s"), Some("N"), "Number of generations between frames; overrides s"),
+    ("--interval", Some("-i"), Some("MS"), "Minimum time between frames, in milliseconds; overrides i"),
+    ("--smoothing", Some("-u"), Some("N"), "Smoothing factor of the interpolation; overrides u, and enables it when rendering"),
+    ("--output", Some("-o"), Some("FILE"), "File to write to"),
+    ("--follow", None, None, "Keep the center of mass of the universe at the center of the window"),
+    ("--trails", None, Some("N"), "Draw the path of the particles over the last N frames behind them; toggled with L"),
+    ("--stamp", None, Some("FILE"), "Pattern to place under the mouse with V while paused"),
+    ("--frames", None, Some("N"), "Number of frames of the animation (default 100)"),
+    ("--subframes", None, Some("N"), "Number of interpolated frames every -s generations (default 1)"),
+    ("--grid", None, None, "Draw the squares of the partition of the current generation (SVG only)"),
+    ("--trajectories", None, Some("N"), "Draw the path of each particle over N generations (SVG only)"),
+    ("--no-fit", None, None, "Center the image on the origin like the window, instead of framing the particles"),
+    ("--width", None, Some("PX"), "Width of the image (default 1366)"),
+    ("--height", None, Some("PX"), "Height of the image (default 768)"),
+    ("--max-period", None, Some("N"), "Number of generations to run each object for when identifying it"),
+    ("--seed", None, Some("SEED"), "Seed of the first soup (default 0)"),
+    ("--size", None, Some("N"), "Side of the soups (default 16)"),
+    ("--density", None, Some("P"), "Probability for each cell of a soup to be alive, between 0 and 1 (default 0.5)"),
+    ("--symmetry", None, Some("SYM"), "Symmetry of the soups: C1, C2, C4 or D2 (default C1)"),
+    ("--emit-soup", None, Some("FILE"), "Write the soup of --seed as RLE instead of running the search"),
+    ("--max-generations", None, Some("N"), "Maximum number of generations to run each soup or collision for"),
+    ("--range", None, Some("N"), "Offsets to place the second ship at, in cells (default 8)"),
+    ("--to", None, Some("FORMAT"), "Format of the written pattern: rle, standard, snapshot, cells or code (default rle)"),
+    ("--stop-when", None, Some("COND"), "Stop once the pattern is periodic or settled (all of its objects are identified)"),
+    ("--check-interval", None, Some("N"), "Number of generations between two checks of --stop-when (default 100)"),
+    ("--quiet", Some("-q"), None, "Don't write the final pattern"),
+    ("--help", Some("-h"), None, "Prints this message"),
+];
+
+/// Options accepted by every subcommand reading a pattern
+const INPUT_FLAGS: [&str; 5] = ["--standard", "--resume", "--jump", "--threads", "--rule"];
+
+/// Maximum number of positional arguments of each subcommand
+fn max_positional(command: Command) -> usize {
+    match command {
+        Command::Collide | Command::Convert => 2,
+        Command::Help => usize::MAX,
+        _ => 1,
+    }
+}
+
+/// Options accepted by each subcommand, on top of `--help`
+fn command_flags(command: Command) -> Vec<&'static str> {
+    let mut res = match command {
+        Command::Run => vec![
+            "--generations",
+            "--until",
+            "--engine",
+            "--stop-when",
+            "--check-interval",
+            "--max-period",
+            "--checkpoint",
+            "--checkpoint-interval",
+            "--to",
+            "--output",
+            "--quiet",
+        ],
+        Command::View => vec![
+            "--window",
+            "--steps",
+            "--interval",
+            "--smoothing",
+            "--checkpoint",
+            "--checkpoint-interval",
+            "--stamp",
+            "--output",
+            "--follow",
+            "--trails",
+        ],
+        Command::Render => vec![
+            "--generations",
+            "--until",
+            "--output",
+            "--width",
+            "--height",
+            "--no-fit",
+            "--frames",
+            "--steps",
+            "--interval",
+            "--smoothing",
+            "--subframes",
+            "--grid",
+            "--trajectories",
+        ],
+        Command::Census => vec!["--generations", "--until", "--engine", "--checkpoint", "--checkpoint-interval", "--max-period"],
+        Command::Analyse => vec!["--max-period"],
+        Command::Search => {
+            return vec!["--help", "--rule", "--seed", "--size", "--density", "--symmetry", "--emit-soup", "--max-generations", "--max-period"]
+        }
+        Command::Collide => return vec!["--help", "--rule", "--range", "--max-generations", "--max-period"],
+        Command::Convert => vec!["--to", "--max-period"],
+        Command::Help => return vec!["--help"],
+    };
+    res.extend_from_slice(&INPUT_FLAGS);
+    res.push("--help");
+    res
+}
+
+/// The parsed command line
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub command: Command,
+    /// Arguments that aren't options, such as file names
+    pub positional: Vec<String>,
+    /// Values of the options, indexed by their long name; options without values hold an empty string
+    options: HashMap<&'static str, String>,
+}
+
+impl Args {
+    /// Parses the arguments, without the name of the binary.
+    /// If the first argument isn't a subcommand, `view` is assumed, so that `single-rotation -g < pattern.rle` still works
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter().peekable();
+        let command = match args.peek().and_then(|arg| COMMANDS.iter().find(|(_, name, _, _)| name == arg)) {
+            Some(&(command, _, _, _)) => {
+                args.next();
+                command
+            }
+            None => Command::View,
+        };
+        let allowed = command_flags(command);
+
+        let mut res = Self {
+            command,
+            positional: Vec::new(),
+            options: HashMap::new(),
+        };
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') || arg == "-" {
+                res.positional.push(arg);
+                continue
+            }
+
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let flag = FLAGS
+                .iter()
+                .find(|(long, short, _, _)| *long == name || *short == Some(name.as_str()))
+                .filter(|(long, _, _, _)| allowed.contains(long))
+                .ok_or_else(|| format!("unknown option {} for {}", name, command_name(command)))?;
+
+            let value = match (flag.2, inline_value) {
+                (Some(_), Some(value)) => value,
+                (Some(_), None) => args.next().ok_or_else(|| format!("expected a value after {}", name))?,
+                (None, Some(_)) => return Err(format!("{} doesn't take a value", flag.0)),
+                (None, None) => String::new(),
+            };
+            res.options.insert(flag.0, value);
+        }
+
+        if res.positional.len() > max_positional(command) {
+            return Err(format!("unexpected argument {} for {}", res.positional[max_positional(command)], command_name(command)))
+        }
+        if res.has("--help") {
+            res.command = Command::Help;
+        }
+        Ok(res)
+    }
+
+    /// Returns true if the option `long` was given
+    pub fn has(&self, long: &str) -> bool {
+        self.options.contains_key(long)
+    }
+
+    /// Returns the value of the option `long`, parsed, if it was given
+    pub fn value<T: FromStr>(&self, long: &str) -> Result<Option<T>, String>
+    where
+        T::Err: std::fmt::Display,
+    {
+        match self.options.get(long) {
+            Some(value) => value
+                .parse::<T>()
+                .map(Some)
+                .map_err(|err| format!("invalid value {:?} for {}: {}", value, long, err)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn command_name(command: Command) -> &'static str {
+    COMMANDS.iter().find(|(c, _, _, _)| *c == command).map(|(_, name, _, _)| *name).unwrap_or("")
+}
+
+/// Returns the help message
+pub fn usage() -> String {
+    let mut res = String::from("Usage: single-rotation <COMMAND> [OPTIONS]\n\n");
+    res.push_str("Patterns are read from FILE, or from the standard input if it is missing or is -.\n\nCommands:\n");
+    for (_, name, arguments, description) in COMMANDS.iter() {
+        let _ = writeln!(res, "  {:<28}{}", format!("{} {}", name, arguments), description);
+    }
+
+    res.push_str("\nOptions:\n");
+    for (long, short, value, description) in FLAGS.iter() {
+        let name = match (short, value) {
+            (Some(short), Some(value)) => format!("{}, {} {}", short, long, value),
+            (Some(short), None) => format!("{}, {}", short, long),
+            (None, Some(value)) => format!("{} {}", long, value),
+            (None, None) => long.to_string(),
+        };
+        let commands = COMMANDS
+            .iter()
+            .filter(|(command, _, _, _)| *command != Command::Help && command_flags(*command).contains(long))
+            .map(|(_, name, _, _)| *name)
+            .collect::<Vec<_>>();
+        let _ = writeln!(res, "  {:<28}{} [{}]", name, description, commands.join(", "));
+    }
+    res
+}
+
+#[test]
+fn test_parse_args() {
+    let parse = |args: &[&str]| Args::parse(args.iter().map(|arg| arg.to_string()));
+
+    let args = parse(&["census", "pattern.rle", "-n", "100", "--max-period=50", "--standard"]).unwrap();
+    assert_eq!(args.command, Command::Census);
+    assert_eq!(args.positional, vec![String::from("pattern.rle")]);
+    assert_eq!(args.value::<usize>("--generations"), Ok(Some(100)));
+    assert_eq!(args.value::<usize>("--max-period"), Ok(Some(50)));
+    assert_eq!(args.value::<usize>("--until"), Ok(None));
+    assert!(args.has("--standard"));
+
+    // Without a subcommand, the pattern is viewed
+    let args = parse(&["-g", "-j", "4"]).unwrap();
+    assert_eq!(args.command, Command::View);
+    assert!(args.has("--window"));
+    assert_eq!(args.value::<usize>("--threads"), Ok(Some(4)));
+    assert!(args.value::<usize>("--window").is_err());
+
+    assert_eq!(parse(&["run", "--help"]).unwrap().command, Command::Help);
+    assert!(parse(&["search", "--checkpoint", "dir"]).is_err());
+    assert!(parse(&["run", "a.rle", "b.rle"]).is_err());
+    assert!(parse(&["render", "-o"]).is_err());
+    assert!(parse(&["run", "-q", "--stop-when", "periodic", "--to", "cells"]).unwrap().has("--quiet"));
+    assert!(parse(&["view", "-g=1"]).is_err());
+}
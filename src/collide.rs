@@ -0,0 +1,288 @@
+use std::fmt;
+use super::analysis::{classify, Classification};
+use super::capture::OBJECT_SEPARATION;
+use super::census::{census, Census};
+use super::regions::RegionTree;
+use super::rules::Symmetry;
+
+/// Parameters of a collision sweep
+#[derive(Debug, Clone)]
+pub struct CollisionConfig {
+    /// The second ship is moved by every even offset between `-range` and `range` on both axes
+    pub range: i64,
+    /// Maximum number of generations to rewind the ships for, until they are far enough apart to be placed together
+    pub max_lead: usize,
+    /// Maximum number of generations to run each collision for, once the ships have met
+    pub max_generations: usize,
+    /// Number of generations between two checks for stabilization
+    pub check_interval: usize,
+    /// Maximum number of generations to run each object for when identifying it
+    pub max_period: usize,
+}
+
+impl Default for CollisionConfig {
+    fn default() -> Self {
+        Self {
+            range: 8,
+            max_lead: 1000,
+            max_generations: 10000,
+            check_interval: 100,
+            max_period: 1000,
+        }
+    }
+}
+
+/// What came out of a collision
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// No object is left
+    Annihilation,
+    /// The ships went through each other, or never met: the result is the same as running each of them on its own
+    NoInteraction,
+    /// Every object that came out could be identified
+    Settled,
+    /// Some objects were still unidentified after `max_generations`
+    Unsettled,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Annihilation => write!(f, "annihilation"),
+            Outcome::NoInteraction => write!(f, "no-interaction"),
+            Outcome::Settled => write!(f, "settled"),
+            Outcome::Unsettled => write!(f, "unsettled"),
+        }
+    }
+}
+
+/// The result of colliding two ships
+#[derive(Debug, Clone)]
+pub struct Collision {
+    /// Horizontal offset of the second ship, relative to the first one, at the time they would have met if they didn't interact
+    pub dx: i64,
+    /// Vertical offset of the second ship; see `dx`
+    pub dy: i64,
+    /// Index in `Symmetry::ALL` of the transformation applied to the second ship
+    pub orientation: usize,
+    /// Number of generations the second ship was advanced by before being placed
+    pub phase: usize,
+    /// Number of generations the ships were rewound by, so that they start apart
+    pub lead: usize,
+    /// Number of generations the collision was run for
+    pub generations: usize,
+    /// What the collision settled into
+    pub outcome: Outcome,
+    /// Objects left once the collision settled
+    pub census: Census,
+}
+
+/// Header of the table written by `Collision`'s `Display` implementation
+pub const TABLE_HEADER: &str = "dx\tdy\torientation\tphase\tlead\tgenerations\toutcome\tships\toscillators\tunclassified";
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut ships = Vec::new();
+        let mut oscillators = Vec::new();
+        for object in &self.census.objects {
+            match (&object.code, object.classification) {
+                (Some(code), Classification::Spaceship(p)) => {
+                    ships.push(format!("{}@({},{})/{}", code, p.dx, p.dy, p.period))
+                }
+                (Some(code), _) => oscillators.push(code.clone()),
+                (None, _) => {}
+            }
+        }
+        ships.sort();
+        oscillators.sort();
+        let list = |list: Vec<String>| if list.is_empty() { String::from("-") } else { list.join(" ") };
+
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.dx,
+            self.dy,
+            self.orientation,
+            self.phase,
+            self.lead,
+            self.generations,
+            self.outcome,
+            list(ships),
+            list(oscillators),
+            self.census.n_unclassified()
+        )
+    }
+}
+
+/// Returns the cells of `tree`, translated by `(dx, dy)`
+fn translated_cells(tree: &RegionTree, dx: i64, dy: i64) -> Vec<(i64, i64)> {
+    tree.particles().map(|(_, (x, y), _)| (x + dx, y + dy)).collect()
+}
+
+/// Returns true if every cell of `a` is more than `OBJECT_SEPARATION` cells away from every cell of `b`
+fn separated(a: &RegionTree, b: &RegionTree) -> bool {
+    a.particles().all(|(_, (ax, ay), _)| {
+        b.particles().all(|(_, (bx, by), _)| (ax - bx).abs().max((ay - by).abs()) > OBJECT_SEPARATION)
+    })
+}
+
+/// Runs a single collision: `a` and `b` hold the two ships at the same step, where they would overlap if they didn't interact.
+/// Both ships are rewound until they are apart, then put together and run until the objects coming out can be identified.
+/// Returns `None` if the ships couldn't be separated within `config.max_lead` generations.
+fn run_collision(a: &RegionTree, b: &RegionTree, config: &CollisionConfig) -> Option<(usize, usize, Outcome, Census)> {
+    let mut a = a.clone();
+    let mut b = b.clone();
+    let mut lead = 0;
+    while !separated(&a, &b) {
+        if lead >= config.max_lead {
+            return None
+        }
+        a.untick();
+        b.untick();
+        lead += 1;
+    }
+    let mut tree = RegionTree::with_rule(a.rule.clone());
+    tree.step = a.step;
+    for (_, (x, y), _) in a.particles() {
+        tree.insert(x, y, 0);
+    }
+    for (_, (x, y), _) in b.particles() {
+        tree.insert(x, y, 1);
+    }
+
+    // Let the ships meet before looking for the outcome
+    for _ in 0..lead {
+        tree.tick();
+        a.tick();
+        b.tick();
+    }
+    let mut generations = lead;
+    let mut previous = None;
+    loop {
+        let objects = census(&tree, config.max_period);
+        if objects.objects.is_empty() {
+            return Some((lead, generations, Outcome::Annihilation, objects))
+        }
+        // The objects may still be close to each other, so the census has to stay the same for a while
+        if objects.n_unclassified() == 0 && previous.as_ref() == Some(&objects.counts) {
+            let mut cells = translated_cells(&tree, 0, 0);
+            let mut apart = translated_cells(&a, 0, 0);
+            apart.extend(translated_cells(&b, 0, 0));
+            cells.sort_unstable();
+            apart.sort_unstable();
+            let outcome = if cells == apart { Outcome::NoInteraction } else { Outcome::Settled };
+            return Some((lead, generations, outcome, objects))
+        }
+        if generations >= lead + config.max_generations {
+            return Some((lead, generations, Outcome::Unsettled, objects))
+        }
+        previous = Some(objects.counts);
+        for _ in 0..config.check_interval.max(1) {
+            tree.tick();
+            a.tick();
+            b.tick();
+            generations += 1;
+        }
+    }
+}
+
+/// Collides the ship in `b` with the ship in `a`, for every even offset within `config.range`, every phase of `b`,
+/// and every orientation of `b` allowed by the rule.
+/// Both trees must contain a single ship each; offsets are relative to their current positions.
+pub fn collide(a: &RegionTree, b: &RegionTree, config: &CollisionConfig) -> Vec<Collision> {
+    let phase_symmetric = a.rule.is_phase_symmetric();
+    let period = match classify(b, config.max_period) {
+        Classification::Oscillator(periodicity) | Classification::Spaceship(periodicity) => periodicity.period,
+        _ => 1,
+    };
+
+    // Both ships are placed at a step that is late enough to be rewound from
+    let base = 2 * config.max_lead + a.step % 2;
+    let mut a = a.clone();
+    a.step = base;
+
+    let mut res = Vec::new();
+    for symmetry in a.rule.symmetries() {
+        let orientation = Symmetry::ALL.iter().position(|&s| s == symmetry).unwrap_or(0);
+        let mut b = {
+            let mut oriented = RegionTree::with_rule(b.rule.clone());
+            oriented.step = b.step;
+            for (_, (x, y), _) in b.particles() {
+                let (x, y) = symmetry.apply(x, y);
+                oriented.insert(x, y, 0);
+            }
+            oriented
+        };
+
+        for phase in 0..period {
+            // Squares of the partition only line up if both ships are at the same phase, or one of them is shifted diagonally
+            let shift = if b.step % 2 == a.step % 2 {
+                Some(0)
+            } else if phase_symmetric {
+                Some(1)
+            } else {
+                None
+            };
+
+            if let Some(shift) = shift {
+                for dy in (-config.range..=config.range).filter(|dy| dy % 2 == 0) {
+                    for dx in (-config.range..=config.range).filter(|dx| dx % 2 == 0) {
+                        let mut placed = RegionTree::with_rule(b.rule.clone());
+                        placed.step = base;
+                        for (x, y) in translated_cells(&b, dx + shift, dy + shift) {
+                            placed.insert(x, y, 0);
+                        }
+
+                        if let Some((lead, generations, outcome, census)) = run_collision(&a, &placed, config) {
+                            res.push(Collision {
+                                dx: dx + shift,
+                                dy: dy + shift,
+                                orientation,
+                                phase,
+                                lead,
+                                generations,
+                                outcome,
+                                census,
+                            });
+                        }
+                    }
+                }
+            }
+
+            b.tick();
+        }
+    }
+
+    res
+}
+
+#[test]
+fn test_collide() {
+    use super::capture::decode;
+
+    // The lightest spaceship, moving up
+    let ship = decode("xq12_4-m", super::rules::BlockRule::default()).unwrap();
+    let config = CollisionConfig {
+        range: 0,
+        max_generations: 500,
+        ..CollisionConfig::default()
+    };
+    let collisions = collide(&ship, &ship, &config);
+    for collision in &collisions {
+        assert_eq!(collision.to_string().split('\t').count(), TABLE_HEADER.split('\t').count());
+    }
+    // Two copies of the ship with the same orientation overlap at every step, so they can't be placed apart
+    assert!(collisions.iter().all(|collision| collision.orientation != 0));
+
+    let find = |orientation: usize, phase: usize| {
+        collisions
+            .iter()
+            .find(|collision| collision.orientation == orientation && collision.phase == phase)
+            .unwrap()
+            .to_string()
+    };
+    // Head-on, the ships can go through each other, leave two oscillators behind, or deflect one of them sideways
+    assert_eq!(find(3, 0), "0\t0\t3\t0\t0\t100\tno-interaction\txq12_4-m@(0,-2)/12 xq12_4-m@(0,2)/12\t-\t0");
+    assert_eq!(find(5, 3), "1\t1\t5\t3\t11\t111\tsettled\t-\txp400_1-e xp40_a-82\t0");
+    assert_eq!(find(6, 11), "1\t1\t6\t11\t36\t236\tsettled\txq12_4-m@(2,0)/12\txp16_1-45\t0");
+}
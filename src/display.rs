@@ -1,13 +1,19 @@
+use std::collections::VecDeque;
 use minifb::{Window, WindowOptions};
-use super::lanczos::LanczosInterpolator;
-use raqote::{DrawTarget, SolidSource, Source, DrawOptions, PathBuilder};
-use std::time::Duration;
+use super::regions::RegionTree;
+use raqote::{DrawTarget, SolidSource, Source, DrawOptions, PathBuilder, StrokeStyle, LineCap, LineJoin};
 
+/// Initial width of the window, in pixels
 pub const WIDTH: usize = 1366;
+/// Initial height of the window, in pixels
 pub const HEIGHT: usize = 768;
+/// Default size of a cell, in pixels
 pub const SCALE: f32 = 16.0;
+/// Radius of the particles, in pixels, when a cell is `SCALE` pixels wide
 pub const RADIUS: f32 = 6.0;
 
+/// Colors of the particles, indexed by their color; the first one is also used for the edges of bounded universes.
+/// Each entry is `(alpha, red, green, blue)`
 pub const COLORS: [(u8, u8, u8, u8); 5] = [
     (0xff, 0xff, 0xff, 0xff),
     (0xff, 0x19, 0x43, 0x50),
@@ -16,29 +22,226 @@ pub const COLORS: [(u8, u8, u8, u8); 5] = [
     (0xff, 0xff, 0x88, 0x82),
 ];
 
+/// Opens the simulation window
 pub fn spawn() -> Window {
     Window::new("Single Rotation CA", WIDTH, HEIGHT, WindowOptions {
+        resize: true,
         ..WindowOptions::default()
     }).unwrap()
 }
 
-pub fn draw(window: &mut Window, tree: &mut LanczosInterpolator, dt: Duration) {
+/// The part of the universe that is shown: the cell at the center of the view, and the size of a cell in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    /// Horizontal coordinate of the center of the view, in cells
+    pub x: f32,
+    /// Vertical coordinate of the center of the view, in cells
+    pub y: f32,
+    /// Size of a cell, in pixels
+    pub scale: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            scale: SCALE,
+        }
+    }
+}
+
+impl Camera {
+    /// Smallest size of a cell, in pixels
+    pub const MIN_SCALE: f32 = 0.05;
+    /// Largest size of a cell, in pixels
+    pub const MAX_SCALE: f32 = 256.0;
+
+    /// Returns where the point `x`, `y` of the universe lies on a view of `width` by `height` pixels
+    pub fn to_screen(&self, (x, y): (f32, f32), (width, height): (usize, usize)) -> (f32, f32) {
+        (
+            (x - self.x) * self.scale + width as f32 / 2.0,
+            (y - self.y) * self.scale + height as f32 / 2.0,
+        )
+    }
+
+    /// Returns the point of the universe under the pixel `x`, `y` of a view of `width` by `height` pixels
+    pub fn to_world(&self, (x, y): (f32, f32), (width, height): (usize, usize)) -> (f32, f32) {
+        (
+            (x - width as f32 / 2.0) / self.scale + self.x,
+            (y - height as f32 / 2.0) / self.scale + self.y,
+        )
+    }
+
+    /// Moves the view by `dx`, `dy` pixels
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        self.x -= dx / self.scale;
+        self.y -= dy / self.scale;
+    }
+
+    /// Zooms in by `factor`, or out if it is below 1, keeping the point under the pixel `around` in place
+    pub fn zoom(&mut self, factor: f32, around: (f32, f32), size: (usize, usize)) {
+        let before = self.to_world(around, size);
+        self.scale = (self.scale * factor).clamp(Self::MIN_SCALE, Self::MAX_SCALE);
+        let after = self.to_world(around, size);
+        self.x += before.0 - after.0;
+        self.y += before.1 - after.1;
+    }
+
+    /// Frames the cells between `(left, top)` and `(right, bottom)` in a view of `width` by `height` pixels, leaving a margin around them
+    pub fn fit(&mut self, (left, top): (f32, f32), (right, bottom): (f32, f32), (width, height): (usize, usize)) {
+        self.x = (left + right) / 2.0;
+        self.y = (top + bottom) / 2.0;
+        let scale_x = width as f32 / (right - left + 4.0);
+        let scale_y = height as f32 / (bottom - top + 4.0);
+        self.scale = scale_x.min(scale_y).clamp(Self::MIN_SCALE, Self::MAX_SCALE);
+    }
+}
+
+/// How the trails left behind particles are drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrailStyle {
+    /// Number of past frames the trails go back to
+    pub length: usize,
+    /// Width of the trails, relative to the radius of the particles
+    pub width: f32,
+    /// Opacity of the newest part of the trails of the particles of each color of `COLORS`; trails fade out towards their end
+    pub opacity: [f32; COLORS.len()],
+}
+
+impl Default for TrailStyle {
+    fn default() -> Self {
+        Self {
+            length: 32,
+            width: 0.5,
+            opacity: [0.6; COLORS.len()],
+        }
+    }
+}
+
+/// The positions of the particles over the last frames, drawn behind them as fading trails
+#[derive(Debug, Clone, Default)]
+pub struct Trails {
+    /// How the trails are drawn
+    pub style: TrailStyle,
+    /// Positions on each frame, newest first
+    history: VecDeque<Vec<(f32, f32)>>,
+}
+
+impl Trails {
+    /// Creates trails drawn with `style`, without any recorded frame
+    pub fn new(style: TrailStyle) -> Self {
+        Self {
+            style,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Records the positions of the particles on a new frame, forgetting the frames that are older than the trails
+    pub fn push(&mut self, positions: &[(f32, f32)]) {
+        self.history.push_front(positions.to_vec());
+        self.history.truncate(self.style.length + 1);
+    }
+
+    /// Forgets every recorded frame, for instance after the particles jumped
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    /// Returns the number of frames that are recorded
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Returns whether no frame is recorded
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+}
+
+/// Draws the particles of `tree` at `positions`, such as the ones returned by `LanczosInterpolator::get`, into `window`,
+/// with their `trails` behind them if there are any
+pub fn draw(window: &mut Window, tree: &RegionTree, positions: &[(f32, f32)], camera: &Camera, trails: Option<&Trails>) {
     let size = window.get_size();
     let mut target = DrawTarget::new(size.0 as i32, size.1 as i32);
 
+    if let Some(trails) = trails {
+        draw_trails(&mut target, tree, trails, camera);
+    }
+    draw_particles(&mut target, tree, positions, camera);
+
+    window.update_with_buffer(target.get_data(), size.0, size.1).unwrap();
+}
+
+/// Renders the particles of `tree` at their current position onto a black image of `width` by `height` pixels, as seen by `camera`
+pub fn render(tree: &RegionTree, width: usize, height: usize, camera: &Camera) -> DrawTarget {
+    let positions = tree.cells.iter().skip(1).map(|&(x, y)| (x as f32, y as f32)).collect::<Vec<_>>();
+    render_positions(tree, &positions, width, height, camera)
+}
+
+/// Renders the particles of `tree` at `positions`, such as the ones returned by `LanczosInterpolator::get`,
+/// onto a black image of `width` by `height` pixels, as seen by `camera`
+pub fn render_positions(tree: &RegionTree, positions: &[(f32, f32)], width: usize, height: usize, camera: &Camera) -> DrawTarget {
+    let mut target = DrawTarget::new(width as i32, height as i32);
+    target.clear(SolidSource::from_unpremultiplied_argb(0xff, 0, 0, 0));
+    draw_particles(&mut target, tree, positions, camera);
+    target
+}
+
+/// Returns the top-left and bottom-right corners of the bounding box of the living particles of `tree` at `positions`,
+/// which are indexed like `tree.cells` without its first, unused slot, or `None` if there is no living particle
+pub fn bounds(tree: &RegionTree, positions: &[(f32, f32)]) -> Option<((f32, f32), (f32, f32))> {
+    let mut bounds: Option<((f32, f32), (f32, f32))> = None;
+    for (i, &(x, y)) in positions.iter().enumerate() {
+        if !tree.is_alive(i + 1) {
+            continue
+        }
+        bounds = Some(match bounds {
+            Some(((left, top), (right, bottom))) => ((left.min(x), top.min(y)), (right.max(x), bottom.max(y))),
+            None => ((x, y), (x, y)),
+        });
+    }
+    bounds
+}
+
+/// Draws the particles of `tree` at `positions`, which are indexed like `tree.cells` without its first, unused slot,
+/// and the edges of bounded universes
+fn draw_particles(target: &mut DrawTarget, tree: &RegionTree, positions: &[(f32, f32)], camera: &Camera) {
     let mut pbs = Vec::new();
     for _ in 0..COLORS.len() {
         pbs.push(PathBuilder::new());
     }
 
-    let cx = size.0 as f32 / 2.0;
-    let cy = size.1 as f32 / 2.0;
+    let size = (target.width() as usize, target.height() as usize);
+    // Particles stay visible when zoomed out
+    let radius = (RADIUS * camera.scale / SCALE).max(0.5);
+
+    for (i, cell) in positions.iter().enumerate() {
+        if !tree.alive[i + 1] {
+            continue
+        }
+        let (x, y) = camera.to_screen(*cell, size);
+        if x < -radius || y < -radius || x > size.0 as f32 + radius || y > size.1 as f32 + radius {
+            continue
+        }
+        // Colors beyond the palette, which snapshots and the library may hold, wrap around it
+        let pb = &mut pbs[tree.colors[i + 1] % COLORS.len()];
+        pb.move_to(x, y);
+        pb.arc(x, y, radius, 0.0, std::f32::consts::PI * 2.0);
+    }
 
-    for (i, cell) in tree.get(dt).into_iter().enumerate() {
-        let x = cell.0 * SCALE + cx;
-        let y = cell.1 * SCALE + cy;
-        pbs[tree.tree.colors[i + 1]].move_to(x, y);
-        pbs[tree.tree.colors[i + 1]].arc(x, y, RADIUS, 0.0, std::f32::consts::PI * 2.0);
+    // Edges of bounded universes
+    if let Some((width, height)) = tree.topology.size() {
+        let mut pb = PathBuilder::new();
+        let (x, y) = camera.to_screen((-0.5, -0.5), size);
+        pb.rect(x, y, width as f32 * camera.scale, height as f32 * camera.scale);
+        let color = COLORS[0];
+        target.stroke(
+            &pb.finish(),
+            &Source::Solid(SolidSource::from_unpremultiplied_argb(color.0 / 2, color.1, color.2, color.3)),
+            &StrokeStyle::default(),
+            &DrawOptions::new()
+        );
     }
 
     for (i, pb) in pbs.into_iter().enumerate() {
@@ -50,5 +253,113 @@ pub fn draw(window: &mut Window, tree: &mut LanczosInterpolator, dt: Duration) {
             &DrawOptions::new()
         );
     }
-    window.update_with_buffer(target.get_data(), size.0, size.1).unwrap();
+}
+
+/// Draws the path of each particle of `tree` over the frames recorded in `trails`, in the color of the particle.
+/// Each segment is more transparent than the one after it; segments crossing the edges of a torus are left out
+fn draw_trails(target: &mut DrawTarget, tree: &RegionTree, trails: &Trails, camera: &Camera) {
+    let length = trails.style.length.min(trails.history.len().saturating_sub(1));
+    if length == 0 {
+        return
+    }
+
+    // One path per color and per age of the segments, so that the segments of each path have the same opacity
+    let mut pbs = (0..COLORS.len() * length).map(|_| PathBuilder::new()).collect::<Vec<_>>();
+    let size = (target.width() as usize, target.height() as usize);
+    let wrap = tree.topology.size().map(|(width, height)| (width as f32 / 2.0, height as f32 / 2.0));
+
+    for (age, (newer, older)) in trails.history.iter().zip(trails.history.iter().skip(1)).take(length).enumerate() {
+        for (i, (&to, &from)) in newer.iter().zip(older.iter()).enumerate() {
+            if !tree.alive.get(i + 1).copied().unwrap_or(false) {
+                continue
+            }
+            if let Some((half_width, half_height)) = wrap {
+                if (to.0 - from.0).abs() > half_width || (to.1 - from.1).abs() > half_height {
+                    continue
+                }
+            }
+            let pb = &mut pbs[tree.colors[i + 1] % COLORS.len() * length + age];
+            let (x, y) = camera.to_screen(from, size);
+            pb.move_to(x, y);
+            let (x, y) = camera.to_screen(to, size);
+            pb.line_to(x, y);
+        }
+    }
+
+    let style = StrokeStyle {
+        width: (RADIUS * trails.style.width * camera.scale / SCALE).max(0.5),
+        cap: LineCap::Round,
+        join: LineJoin::Round,
+        ..StrokeStyle::default()
+    };
+    for (i, pb) in pbs.into_iter().enumerate() {
+        let (color, age) = (i / length, i % length);
+        let opacity = trails.style.opacity[color] * (1.0 - age as f32 / length as f32);
+        let alpha = (opacity.clamp(0.0, 1.0) * 255.0) as u8;
+        if alpha == 0 {
+            continue
+        }
+        let (_, r, g, b) = COLORS[color];
+        target.stroke(
+            &pb.finish(),
+            &Source::Solid(SolidSource::from_unpremultiplied_argb(alpha, r, g, b)),
+            &style,
+            &DrawOptions::new()
+        );
+    }
+}
+
+#[test]
+fn test_camera() {
+    let size = (200, 100);
+    let mut camera = Camera::default();
+    assert_eq!(camera.to_screen((0.0, 0.0), size), (100.0, 50.0));
+    assert_eq!(camera.to_world((116.0, 34.0), size), (1.0, -1.0));
+
+    // Zooming keeps the point under the cursor in place
+    camera.zoom(2.0, (116.0, 34.0), size);
+    assert_eq!(camera.scale, 32.0);
+    assert_eq!(camera.to_screen((1.0, -1.0), size), (116.0, 34.0));
+
+    camera.pan(32.0, 0.0);
+    assert_eq!(camera.to_screen((1.0, -1.0), size), (148.0, 34.0));
+
+    camera.fit((-10.0, 0.0), (10.0, 4.0), size);
+    assert_eq!((camera.x, camera.y), (0.0, 2.0));
+    assert_eq!(camera.scale, 200.0 / 24.0);
+}
+
+#[test]
+fn test_trails() {
+    let mut tree = RegionTree::new();
+    tree.insert(0, 0, 1);
+    let mut trails = Trails::new(TrailStyle {
+        length: 4,
+        ..TrailStyle::default()
+    });
+    for x in (0..8).rev() {
+        trails.push(&[(x as f32, 0.0)]);
+    }
+    assert_eq!(trails.len(), 5);
+
+    let mut target = DrawTarget::new(200, 100);
+    target.clear(SolidSource::from_unpremultiplied_argb(0xff, 0, 0, 0));
+    draw_trails(&mut target, &tree, &trails, &Camera::default());
+    let pixel = |x: usize, y: usize| target.get_data()[y * 200 + x] & 0xffffff;
+    // The trail goes from (4, 0) to the particle at (0, 0), fading out
+    assert_ne!(pixel(100 + 16, 50), 0);
+    assert!(pixel(100 + 16, 50) > pixel(100 + 56, 50));
+    assert_eq!(pixel(100 + 80, 50), 0);
+    assert_eq!(pixel(100 + 16, 70), 0);
+
+    // Colors beyond the palette wrap around it
+    let mut tree = RegionTree::new();
+    tree.insert(0, 0, COLORS.len() + 1);
+    trails.clear();
+    trails.push(&[(0.0, 0.0)]);
+    trails.push(&[(1.0, 0.0)]);
+    let target = render(&tree, 200, 100, &Camera::default());
+    assert_ne!(target.get_data()[50 * 200 + 100] & 0xffffff, 0);
+    let mut target = DrawTarget::new(200, 100);
+    draw_trails(&mut target, &tree, &trails, &Camera::default());
 }
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use raqote::DrawTarget;
+use super::display::{self, Camera, COLORS};
+use super::lanczos::LanczosInterpolator;
+use super::regions::RegionTree;
+
+/// Order of the Lanczos filter, the same as in the window
+const LANCZOS_ORDER: usize = 3;
+
+/// Parameters of an offscreen rendering
+#[derive(Debug, Clone)]
+pub struct AnimationConfig {
+    /// Width of the frames, in pixels
+    pub width: usize,
+    /// Height of the frames, in pixels
+    pub height: usize,
+    /// Number of frames to render
+    pub frames: usize,
+    /// Number of generations between two frames, or between two groups of `subframes` frames when interpolating
+    pub steps: usize,
+    /// Smoothing factor of the Lanczos interpolation, like `u` in RLE files; positions aren't interpolated if `None`
+    pub smoothing: Option<usize>,
+    /// Number of interpolated frames every `steps` generations
+    pub subframes: usize,
+    /// View of the universe; if `None`, the frames are fitted to the particles over the whole animation,
+    /// or to the universe if it is bounded
+    pub camera: Option<Camera>,
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            width: display::WIDTH,
+            height: display::HEIGHT,
+            frames: 1,
+            steps: 1,
+            smoothing: None,
+            subframes: 1,
+            camera: None,
+        }
+    }
+}
+
+/// Returns a camera framing every particle of `tree` on each generation shown by the frames of `config`,
+/// or the whole universe if it is bounded
+fn fit_camera(tree: &RegionTree, config: &AnimationConfig) -> Camera {
+    let mut camera = Camera::default();
+    let size = (config.width, config.height);
+    if let Some((width, height)) = tree.topology().size() {
+        camera.fit((-0.5, -0.5), (width as f32 - 0.5, height as f32 - 0.5), size);
+        return camera
+    }
+
+    // Interpolated frames move by `steps` generations every `subframes` frames
+    let frames = config.frames.saturating_sub(1);
+    let generations = match config.smoothing {
+        Some(_) => frames.div_ceil(config.subframes.max(1)) * config.steps,
+        None => frames * config.steps,
+    };
+    let mut tree = tree.clone();
+    let mut bounds: Option<((f32, f32), (f32, f32))> = None;
+    for i in 0..=generations {
+        if i > 0 {
+            tree.tick();
+        }
+        let positions = tree.cells.iter().skip(1).map(|&(x, y)| (x as f32, y as f32)).collect::<Vec<_>>();
+        bounds = match (bounds, display::bounds(&tree, &positions)) {
+            (Some(((left, top), (right, bottom))), Some(((l, t), (r, b)))) => {
+                Some(((left.min(l), top.min(t)), (right.max(r), bottom.max(b))))
+            }
+            (bounds, new) => bounds.or(new),
+        };
+    }
+    if let Some((top_left, bottom_right)) = bounds {
+        camera.fit(top_left, bottom_right, size);
+    }
+    camera
+}
+
+/// Renders the frames of `tree` described by `config`, without opening a window, and passes them to `frame` one by one.
+/// The first frame shows the current generation of `tree`
+pub fn animate<F>(mut tree: RegionTree, config: &AnimationConfig, mut frame: F) -> io::Result<()>
+where
+    F: FnMut(&DrawTarget) -> io::Result<()>,
+{
+    let camera = config.camera.unwrap_or_else(|| fit_camera(&tree, config));
+    let smoothing = match config.smoothing {
+        Some(smoothing) => smoothing.max(1),
+        None => {
+            for i in 0..config.frames {
+                if i > 0 {
+                    for _ in 0..config.steps {
+                        tree.tick();
+                    }
+                }
+                frame(&display::render(&tree, config.width, config.height, &camera))?;
+            }
+            return Ok(())
+        }
+    };
+
+    let subframes = config.subframes.max(1);
+    let mut interpolator = LanczosInterpolator::new(tree, LANCZOS_ORDER, subframes, smoothing, subframes as u32, config.steps);
+    // Start from the current generation, rather than from the ones the interpolator needs to know about before it
+    interpolator.set_speed(config.steps, false);
+    for i in 0..config.frames {
+        // Each millisecond is one subframe
+        let positions = interpolator.get(Duration::from_millis(if i == 0 { 0 } else { 1 }));
+        frame(&display::render_positions(interpolator.tree(), &positions, config.width, config.height, &camera))?;
+    }
+    Ok(())
+}
+
+/// Renders the frame of `tree` described by `config` into a PNG image at `path`
+pub fn write_png(tree: RegionTree, config: &AnimationConfig, path: &Path) -> io::Result<()> {
+    let config = AnimationConfig {
+        frames: 1,
+        ..config.clone()
+    };
+    animate(tree, &config, |target| {
+        target.write_png(path).map_err(|err| io::Error::other(err.to_string()))
+    })
+}
+
+/// Renders the frames of `tree` described by `config` into an animated GIF, showing each frame for `delay` milliseconds
+pub fn write_gif<W: Write>(tree: RegionTree, config: &AnimationConfig, delay: u32, writer: W) -> io::Result<W> {
+    let mut gif = GifWriter::new(writer, config.width, config.height, delay)?;
+    animate(tree, config, |target| gif.write_frame(target))?;
+    gif.finish()
+}
+
+/// Number of shades of each color of `COLORS` in the palette of GIF files
+const GIF_SHADES: usize = 51;
+
+/// Writes animated GIF files, which loop forever.
+/// Frames are rendered on a black background, so the palette holds black and shades of each color of `COLORS` over it,
+/// and every pixel is mapped to the closest color of the palette
+pub struct GifWriter<W: Write> {
+    writer: W,
+    width: u16,
+    height: u16,
+    /// Delay between frames, in hundredths of a second
+    delay: u16,
+    palette: Vec<(u8, u8, u8)>,
+    /// Index in the palette of the pixels seen so far
+    indices: HashMap<u32, u8>,
+}
+
+impl<W: Write> GifWriter<W> {
+    /// Writes the header of a GIF of `width` by `height` pixels, whose frames are shown for `delay` milliseconds
+    pub fn new(mut writer: W, width: usize, height: usize, delay: u32) -> io::Result<Self> {
+        let too_large = |_| io::Error::new(io::ErrorKind::InvalidInput, "GIF images are at most 65535 pixels wide");
+        let width = u16::try_from(width).map_err(too_large)?;
+        let height = u16::try_from(height).map_err(too_large)?;
+
+        let mut palette = vec![(0, 0, 0)];
+        for &(_, r, g, b) in COLORS.iter() {
+            for shade in 1..=GIF_SHADES {
+                let scale = |c: u8| (c as usize * shade / GIF_SHADES) as u8;
+                palette.push((scale(r), scale(g), scale(b)));
+            }
+        }
+        palette.resize(256, (0, 0, 0));
+
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        // Global palette of 256 colors, background color and pixel aspect ratio
+        writer.write_all(&[0xf7, 0, 0])?;
+        for &(r, g, b) in palette.iter() {
+            writer.write_all(&[r, g, b])?;
+        }
+        // Loop forever
+        writer.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        Ok(Self {
+            writer,
+            width,
+            height,
+            delay: (delay / 10).min(u16::MAX as u32) as u16,
+            palette,
+            indices: HashMap::new(),
+        })
+    }
+
+    /// Appends a frame, which must have the size given to `new`
+    pub fn write_frame(&mut self, target: &DrawTarget) -> io::Result<()> {
+        if target.width() != self.width as i32 || target.height() != self.height as i32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the frame doesn't have the size of the GIF"))
+        }
+
+        let palette = &self.palette;
+        let indices = &mut self.indices;
+        let pixels = target
+            .get_data()
+            .iter()
+            .map(|&pixel| *indices.entry(pixel & 0xffffff).or_insert_with(|| closest_color(palette, pixel)))
+            .collect::<Vec<_>>();
+
+        // Graphic control extension, holding the delay
+        self.writer.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+        self.writer.write_all(&self.delay.to_le_bytes())?;
+        self.writer.write_all(&[0x00, 0x00])?;
+        // Image descriptor, covering the whole image
+        self.writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.writer.write_all(&self.width.to_le_bytes())?;
+        self.writer.write_all(&self.height.to_le_bytes())?;
+        self.writer.write_all(&[0x00, 8])?;
+        for block in lzw_encode(&pixels).chunks(255) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0])
+    }
+
+    /// Ends the file, and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Returns the index of the color of `palette` closest to `pixel`, given as ARGB
+fn closest_color(palette: &[(u8, u8, u8)], pixel: u32) -> u8 {
+    let (r, g, b) = ((pixel >> 16) as u8 as i32, (pixel >> 8) as u8 as i32, pixel as u8 as i32);
+    let distance = |&(pr, pg, pb): &(u8, u8, u8)| (pr as i32 - r).pow(2) + (pg as i32 - g).pow(2) + (pb as i32 - b).pow(2);
+    (0..palette.len()).min_by_key(|&i| distance(&palette[i])).unwrap_or(0) as u8
+}
+
+/// Compresses palette indices with the variable-length LZW of GIF files, with a minimum code size of 8 bits
+fn lzw_encode(pixels: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    const MAX_CODES: u16 = 4096;
+
+    let mut bits = BitWriter::default();
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = END + 1;
+    let mut size = 9;
+    bits.write(CLEAR, size);
+
+    let mut prefix: Option<u16> = None;
+    for &pixel in pixels {
+        let current = match prefix {
+            None => {
+                prefix = Some(pixel as u16);
+                continue
+            }
+            Some(current) => current,
+        };
+        if let Some(&code) = codes.get(&(current, pixel)) {
+            prefix = Some(code);
+            continue
+        }
+
+        bits.write(current, size);
+        if next_code == MAX_CODES {
+            bits.write(CLEAR, size);
+            codes.clear();
+            next_code = END + 1;
+            size = 9;
+        } else {
+            if next_code >= 1 << size {
+                size += 1;
+            }
+            codes.insert((current, pixel), next_code);
+            next_code += 1;
+        }
+        prefix = Some(pixel as u16);
+    }
+    if let Some(current) = prefix {
+        bits.write(current, size);
+        // The decoder adds an entry for the code that was just written, and widens its codes if the table is then full
+        if next_code == 1 << size && size < 12 {
+            size += 1;
+        }
+    }
+    bits.write(END, size);
+    bits.finish()
+}
+
+/// Packs codes into bytes, least significant bits first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    n_bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.n_bits;
+        self.n_bits += size;
+        while self.n_bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.n_bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n_bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[test]
+fn test_gif() {
+    let mut tree = RegionTree::new();
+    tree.insert(0, 0, 1);
+    tree.insert(0, 1, 2);
+    let config = AnimationConfig {
+        width: 40,
+        height: 30,
+        frames: 3,
+        steps: 2,
+        smoothing: Some(4),
+        subframes: 2,
+        camera: None,
+    };
+    let gif = write_gif(tree, &config, 50, Vec::new()).unwrap();
+
+    assert_eq!(&gif[..6], b"GIF89a");
+    assert_eq!(&gif[6..10], &[40, 0, 30, 0]);
+    assert_eq!(gif.last(), Some(&0x3b));
+    assert_eq!(gif.windows(4).filter(|window| window == &[0x21, 0xf9, 0x04, 0x00]).count(), 3);
+
+    // A single color compresses into a short, repetitive stream
+    let encoded = lzw_encode(&[7; 1000]);
+    assert!(encoded.len() < 100);
+    assert_eq!(encoded[0], 0x00);
+}
+
+#[test]
+fn test_fit_camera() {
+    // A pattern far from the origin is framed, over all of the generations shown
+    let mut tree = RegionTree::new();
+    tree.insert(1000, 500, 0);
+    tree.insert(1001, 500, 0);
+    tree.insert(1000, 502, 0);
+    tree.insert(1001, 502, 0);
+    let config = AnimationConfig {
+        width: 200,
+        height: 100,
+        frames: 11,
+        steps: 10,
+        ..AnimationConfig::default()
+    };
+    let camera = fit_camera(&tree, &config);
+    let mut last = tree.clone();
+    for _ in 0..100 {
+        last.tick();
+    }
+    for tree in [&tree, &last] {
+        for (_, (x, y), _) in tree.particles() {
+            let (x, y) = camera.to_screen((x as f32, y as f32), (200, 100));
+            assert!((0.0..200.0).contains(&x) && (0.0..100.0).contains(&y), "{:?}", (x, y));
+        }
+    }
+
+    let lit = |config: &AnimationConfig| {
+        let mut lit = false;
+        animate(tree.clone(), config, |target| {
+            lit |= target.get_data().iter().any(|&pixel| pixel & 0xffffff != 0);
+            Ok(())
+        }).unwrap();
+        lit
+    };
+    assert!(lit(&config));
+    assert!(!lit(&AnimationConfig {
+        camera: Some(Camera::default()),
+        ..config.clone()
+    }));
+
+    // Bounded universes are framed whole
+    tree.set_topology(crate::regions::Topology::Torus { width: 2000, height: 1000 });
+    let camera = fit_camera(&tree, &config);
+    assert_eq!((camera.x, camera.y), (999.5, 499.5));
+}
+
+#[test]
+fn test_lzw() {
+    // A straightforward GIF decoder, with a minimum code size of 8 bits
+    fn decode(bytes: &[u8]) -> Vec<u8> {
+        let mut position = 0;
+        let mut read = |size: usize| {
+            let mut code = 0;
+            for i in 0..size {
+                let byte = bytes.get((position + i) / 8).expect("The stream ended before the end code");
+                let bit = byte >> ((position + i) % 8) & 1;
+                code |= (bit as usize) << i;
+            }
+            position += size;
+            code
+        };
+        let mut table: Vec<Vec<u8>> = (0..=255).map(|pixel| vec![pixel]).collect();
+        table.extend([vec![], vec![]]);
+        let mut size = 9;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut res = Vec::new();
+        loop {
+            match read(size) {
+                256 => {
+                    table.truncate(258);
+                    size = 9;
+                    previous = None;
+                }
+                257 => return res,
+                code => {
+                    let entry = match (table.get(code), &previous) {
+                        (Some(entry), _) => entry.clone(),
+                        (None, Some(previous)) if code == table.len() => {
+                            let mut entry = previous.clone();
+                            entry.push(previous[0]);
+                            entry
+                        }
+                        _ => panic!("Invalid code {}", code),
+                    };
+                    res.extend_from_slice(&entry);
+                    if let Some(mut previous) = previous.take() {
+                        if table.len() < 4096 {
+                            previous.push(entry[0]);
+                            table.push(previous);
+                        }
+                    }
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                    previous = Some(entry);
+                }
+            }
+        }
+    }
+
+    // No pair of consecutive pixels repeats, so every pixel gets its own code. The table is full and cleared after 3839 pixels,
+    // and the stream ends right as it reaches 512, 1024 or 2048 codes after 255, 767 and 1791 pixels, then again 3839 and 7678 pixels later
+    let pixels = (0..=255u8).flat_map(|a| (a..=255).skip(1).flat_map(move |b| [a, b])).collect::<Vec<_>>();
+    for length in [255, 767, 1791, 3838, 3839, 3840, 4094, 4606, 5630, 7933, 8445, 9469] {
+        assert_eq!(decode(&lzw_encode(&pixels[..length])), &pixels[..length]);
+    }
+    assert_eq!(decode(&lzw_encode(&pixels)), pixels);
+    assert_eq!(decode(&lzw_encode(&[7; 1000])), vec![7; 1000]);
+}
@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use super::regions::RegionTree;
+use super::rules::{BlockRule, permute};
+
+/// Index of a node within a `HashLife` universe
+pub type NodeId = usize;
+
+/// A node of the quadtree; nodes are hash-consed, so two equal nodes always share the same `NodeId`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Node {
+    /// A 2x2 square, as an occupancy mask (bit 0 is the top-left cell, then clockwise, like in `BlockRule`)
+    Leaf(u8),
+    /// A square of side `2^level`, split into its north-west, north-east, south-west and south-east quadrants
+    Branch(u8, [NodeId; 4]),
+}
+
+/// A memoized quadtree engine for the Margolus neighborhood, able to jump `2^k` generations at once.
+///
+/// Since the partition alternates between two phases, every result is memoized together with the phase it was computed for.
+/// The quadtree only records which cells are alive: particle identities and colors are lost when importing from a `RegionTree`.
+#[derive(Debug, Clone)]
+pub struct HashLife {
+    nodes: Vec<(Node, u64)>,
+    lookup: HashMap<Node, NodeId>,
+    results: HashMap<(NodeId, u8, usize), NodeId>,
+    empty: Vec<NodeId>,
+
+    root: NodeId,
+    /// Coordinates of the top-left corner of the root node; they are always even, to stay aligned with the partition
+    x: i64,
+    y: i64,
+    /// Current generation
+    pub step: usize,
+    /// Rule the universe evolves with; `results` only hold for it, so it is changed through `set_rule`
+    rule: BlockRule,
+}
+
+impl HashLife {
+    /// Creates a new, empty universe following `rule`
+    pub fn new(rule: BlockRule) -> Self {
+        let mut res = Self {
+            nodes: Vec::new(),
+            lookup: HashMap::new(),
+            results: HashMap::new(),
+            empty: Vec::new(),
+            root: 0,
+            x: -4,
+            y: -4,
+            step: 0,
+            rule,
+        };
+        res.root = res.empty_node(3);
+        res
+    }
+
+    /// Imports the cells, rule and step of a `RegionTree`.
+    /// Its topology is ignored, as this engine always simulates an unbounded plane
+    pub fn from_tree(tree: &RegionTree) -> Self {
+        let mut res = Self::new(tree.rule.clone());
+        res.step = tree.step;
+        for region in tree.regions.iter().filter(|region| region.n_cells > 0) {
+            for (y, row) in region.cells.iter().enumerate() {
+                for (x, &cell) in row.iter().enumerate() {
+                    if cell > 0 {
+                        res.set(region.x + x as i64, region.y + y as i64, true);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Exports the universe into a new `RegionTree`; every particle is given the color `0`
+    pub fn to_tree(&self) -> RegionTree {
+        let mut tree = RegionTree::with_rule(self.rule.clone());
+        tree.insert_all(self.cells().into_iter().map(|position| (position, 0)));
+        tree.step = self.step;
+        tree
+    }
+
+    /// Returns the rule the universe evolves with
+    pub fn rule(&self) -> &BlockRule {
+        &self.rule
+    }
+
+    /// Changes the rule the universe evolves with, forgetting the results memoized for the previous one
+    pub fn set_rule(&mut self, rule: BlockRule) {
+        self.results.clear();
+        self.rule = rule;
+    }
+
+    /// Returns the coordinates of all of the live cells
+    pub fn cells(&self) -> Vec<(i64, i64)> {
+        let mut res = Vec::with_capacity(self.population() as usize);
+        self.collect_cells(self.root, self.x, self.y, &mut res);
+        res
+    }
+
+    /// Returns the number of live cells
+    pub fn population(&self) -> u64 {
+        self.nodes[self.root].1
+    }
+
+    /// Returns the number of nodes currently allocated
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns whether or not the cell at `x`, `y` is alive
+    pub fn get(&self, x: i64, y: i64) -> bool {
+        let size = 1 << self.level(self.root);
+        if x < self.x || y < self.y || x >= self.x + size || y >= self.y + size {
+            return false
+        }
+
+        let mut node = self.root;
+        let (mut x, mut y) = (x - self.x, y - self.y);
+        loop {
+            match self.nodes[node].0 {
+                Node::Leaf(mask) => return (mask >> slot(x, y)) & 1 == 1,
+                Node::Branch(level, children) => {
+                    let half = 1 << (level - 1);
+                    node = children[quadrant(x >= half, y >= half)];
+                    x %= half;
+                    y %= half;
+                }
+            }
+        }
+    }
+
+    /// Sets the cell at `x`, `y`, expanding the universe if needed
+    pub fn set(&mut self, x: i64, y: i64, alive: bool) {
+        loop {
+            let size = 1 << self.level(self.root);
+            if x >= self.x && y >= self.y && x < self.x + size && y < self.y + size {
+                break
+            }
+            self.expand();
+        }
+        self.root = self.set_in(self.root, x - self.x, y - self.y, alive);
+    }
+
+    /// Steps the simulation forward by `generations`, jumping by powers of two
+    pub fn step_by(&mut self, generations: usize) {
+        for k in 0..usize::BITS {
+            if (generations >> k) & 1 == 1 {
+                self.step_pow2(k as u8);
+            }
+        }
+    }
+
+    /// Steps the simulation forward by `2^k` generations
+    pub fn step_pow2(&mut self, k: u8) {
+        // Make sure that the pattern lies in the center quarter of the root and that the root is big enough:
+        // cells then can't travel out of the center half, which is what `advance` returns
+        while self.level(self.root) < k + 3 || !self.is_padded(self.root) {
+            self.expand();
+        }
+        self.expand();
+
+        let size = 1i64 << self.level(self.root);
+        self.root = self.advance(self.root, k, self.step % 2);
+        self.x += size / 4;
+        self.y += size / 4;
+        self.step += 1 << k;
+    }
+
+    /// Forgets the memoized results, to free up some memory
+    pub fn clear_cache(&mut self) {
+        self.results.clear();
+    }
+
+    fn level(&self, node: NodeId) -> u8 {
+        match self.nodes[node].0 {
+            Node::Leaf(_) => 1,
+            Node::Branch(level, _) => level,
+        }
+    }
+
+    fn children(&self, node: NodeId) -> [NodeId; 4] {
+        match self.nodes[node].0 {
+            Node::Branch(_, children) => children,
+            Node::Leaf(_) => panic!("Leaves have no children"),
+        }
+    }
+
+    /// Returns the `NodeId` of `node`, allocating it if it doesn't exist yet
+    fn intern(&mut self, node: Node) -> NodeId {
+        if let Some(&id) = self.lookup.get(&node) {
+            return id
+        }
+        let population = match node {
+            Node::Leaf(mask) => mask.count_ones() as u64,
+            Node::Branch(_, children) => children.iter().map(|&child| self.nodes[child].1).sum(),
+        };
+        let id = self.nodes.len();
+        self.nodes.push((node, population));
+        self.lookup.insert(node, id);
+        id
+    }
+
+    fn leaf(&mut self, mask: u8) -> NodeId {
+        self.intern(Node::Leaf(mask))
+    }
+
+    fn branch(&mut self, children: [NodeId; 4]) -> NodeId {
+        let level = self.level(children[0]) + 1;
+        self.intern(Node::Branch(level, children))
+    }
+
+    fn empty_node(&mut self, level: u8) -> NodeId {
+        while self.empty.len() < level as usize {
+            let node = if self.empty.is_empty() {
+                self.leaf(0)
+            } else {
+                let child = self.empty[self.empty.len() - 1];
+                self.branch([child; 4])
+            };
+            self.empty.push(node);
+        }
+        self.empty[level as usize - 1]
+    }
+
+    /// Doubles the size of the root, keeping its contents centered
+    fn expand(&mut self) {
+        let level = self.level(self.root);
+        let empty = self.empty_node(level - 1);
+        let [nw, ne, sw, se] = self.children(self.root);
+        let children = [
+            self.branch([empty, empty, empty, nw]),
+            self.branch([empty, empty, ne, empty]),
+            self.branch([empty, sw, empty, empty]),
+            self.branch([se, empty, empty, empty]),
+        ];
+        self.root = self.branch(children);
+        let size = 1i64 << level;
+        self.x -= size / 2;
+        self.y -= size / 2;
+    }
+
+    /// Returns true if all of the live cells of `node` lie in its center half
+    fn is_padded(&self, node: NodeId) -> bool {
+        let children = self.children(node);
+        // Index, within each quadrant, of the grandchild touching the center
+        let inner = [3, 2, 1, 0];
+        children.iter().zip(inner.iter()).all(|(&child, &inner)| {
+            self.children(child)
+                .iter()
+                .enumerate()
+                .all(|(i, &grandchild)| i == inner || self.nodes[grandchild].1 == 0)
+        })
+    }
+
+    fn set_in(&mut self, node: NodeId, x: i64, y: i64, alive: bool) -> NodeId {
+        match self.nodes[node].0 {
+            Node::Leaf(mask) => {
+                let bit = 1 << slot(x, y);
+                self.leaf(if alive { mask | bit } else { mask & !bit })
+            }
+            Node::Branch(level, mut children) => {
+                let half = 1 << (level - 1);
+                let q = quadrant(x >= half, y >= half);
+                children[q] = self.set_in(children[q], x % half, y % half, alive);
+                self.branch(children)
+            }
+        }
+    }
+
+    fn collect_cells(&self, node: NodeId, x: i64, y: i64, res: &mut Vec<(i64, i64)>) {
+        if self.nodes[node].1 == 0 {
+            return
+        }
+        match self.nodes[node].0 {
+            Node::Leaf(mask) => {
+                for (dx, dy) in [(0, 0), (1, 0), (1, 1), (0, 1)] {
+                    if (mask >> slot(dx, dy)) & 1 == 1 {
+                        res.push((x + dx, y + dy));
+                    }
+                }
+            }
+            Node::Branch(level, children) => {
+                let half = 1 << (level - 1);
+                for (q, &child) in children.iter().enumerate() {
+                    self.collect_cells(child, x + (q as i64 % 2) * half, y + (q as i64 / 2) * half, res);
+                }
+            }
+        }
+    }
+
+    /// Returns the node of level `level - 1` at the center of `node`
+    fn centre(&mut self, node: NodeId) -> NodeId {
+        let [nw, ne, sw, se] = self.children(node);
+        let children = [self.children(nw)[3], self.children(ne)[2], self.children(sw)[1], self.children(se)[0]];
+        self.branch(children)
+    }
+
+    /// Returns the 9 overlapping nodes of level `level - 1` within `node`, in reading order
+    fn subsquares(&mut self, node: NodeId) -> [NodeId; 9] {
+        let [nw, ne, sw, se] = self.children(node);
+        let [_, nw_ne, nw_sw, nw_se] = self.children(nw);
+        let [ne_nw, _, ne_sw, ne_se] = self.children(ne);
+        let [sw_nw, sw_ne, _, sw_se] = self.children(sw);
+        let [se_nw, se_ne, se_sw, _] = self.children(se);
+        [
+            nw,
+            self.branch([nw_ne, ne_nw, nw_se, ne_sw]),
+            ne,
+            self.branch([nw_sw, nw_se, sw_nw, sw_ne]),
+            self.branch([nw_se, ne_sw, sw_ne, se_nw]),
+            self.branch([ne_sw, ne_se, se_nw, se_ne]),
+            sw,
+            self.branch([sw_ne, se_nw, sw_se, se_sw]),
+            se,
+        ]
+    }
+
+    /// Returns the center half of `node` (of level `level - 1`), `2^k` generations later, starting in `phase`.
+    /// `k` must not exceed `level - 2`.
+    fn advance(&mut self, node: NodeId, k: u8, phase: usize) -> NodeId {
+        let level = self.level(node);
+        debug_assert!(level >= 3 && k + 2 <= level);
+
+        if self.nodes[node].1 == 0 {
+            return self.empty_node(level - 1)
+        }
+        if let Some(&res) = self.results.get(&(node, k, phase)) {
+            return res
+        }
+
+        let res = if level == 3 {
+            self.advance_base(node, k, phase)
+        } else {
+            let squares = self.subsquares(node);
+            let full_speed = k + 2 == level;
+
+            // Either advance the 9 subsquares by half of the generations, or just take their center
+            let mut inner = [0; 9];
+            for (i, &square) in squares.iter().enumerate() {
+                inner[i] = if full_speed {
+                    self.advance(square, k - 1, phase)
+                } else {
+                    self.centre(square)
+                };
+            }
+            let (k, phase) = if full_speed {
+                (k - 1, phase ^ (k == 1) as usize)
+            } else {
+                (k, phase)
+            };
+
+            let mut quadrants = [0; 4];
+            for (q, quadrant) in quadrants.iter_mut().enumerate() {
+                let (qx, qy) = (q % 2, q / 2);
+                let children = [
+                    inner[qx + qy * 3],
+                    inner[qx + 1 + qy * 3],
+                    inner[qx + (qy + 1) * 3],
+                    inner[qx + 1 + (qy + 1) * 3],
+                ];
+                let square = self.branch(children);
+                *quadrant = self.advance(square, k, phase);
+            }
+            self.branch(quadrants)
+        };
+
+        self.results.insert((node, k, phase), res);
+        res
+    }
+
+    /// Directly simulates a node of level 3 (8x8 cells) for 1 or 2 generations, returning its 4x4 center
+    fn advance_base(&mut self, node: NodeId, k: u8, phase: usize) -> NodeId {
+        let mut grid = [[false; 8]; 8];
+        let mut cells = Vec::new();
+        self.collect_cells(node, 0, 0, &mut cells);
+        for (x, y) in cells {
+            grid[y as usize][x as usize] = true;
+        }
+
+        for generation in 0..(1 << k) {
+            let table = self.rule.table[(phase + generation) % 2];
+            let offset = (phase + generation) % 2;
+            for y in (offset..7).step_by(2) {
+                for x in (offset..7).step_by(2) {
+                    let square = [grid[y][x], grid[y][x + 1], grid[y + 1][x + 1], grid[y + 1][x]];
+                    let mask = square.iter().enumerate().fold(0, |mask, (i, &alive)| mask | (alive as usize) << i);
+                    let [a, b, c, d] = permute(square, table[mask]);
+                    grid[y][x] = a;
+                    grid[y][x + 1] = b;
+                    grid[y + 1][x + 1] = c;
+                    grid[y + 1][x] = d;
+                }
+            }
+        }
+
+        let mut leaves = [0; 4];
+        for (q, leaf) in leaves.iter_mut().enumerate() {
+            let (x, y) = (2 + (q % 2) * 2, 2 + (q / 2) * 2);
+            let mask = grid[y][x] as u8
+                | (grid[y][x + 1] as u8) << 1
+                | (grid[y + 1][x + 1] as u8) << 2
+                | (grid[y + 1][x] as u8) << 3;
+            *leaf = self.leaf(mask);
+        }
+        self.branch(leaves)
+    }
+}
+
+/// Returns the index of the quadrant, given whether the point lies on its east and south halves
+fn quadrant(east: bool, south: bool) -> usize {
+    east as usize + 2 * south as usize
+}
+
+/// Returns the slot of a cell within a leaf, following the clockwise order of `BlockRule`
+fn slot(x: i64, y: i64) -> u8 {
+    match (x, y) {
+        (0, 0) => 0,
+        (1, 0) => 1,
+        (1, 1) => 2,
+        _ => 3,
+    }
+}
+
+#[test]
+fn test_hashlife() {
+    for rule in &["single-rotation", "critters"] {
+        let mut tree = RegionTree::with_rule(rule.parse().unwrap());
+        let mut seed = 987654321u64;
+        for _ in 0..60 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            tree.insert((seed % 24) as i64 - 12, ((seed >> 32) % 24) as i64 - 12, 0);
+        }
+        tree.step = 1;
+
+        let mut hashlife = HashLife::from_tree(&tree);
+        hashlife.step_by(300);
+        for _ in 0..300 {
+            tree.tick();
+        }
+
+        let mut expected = HashLife::from_tree(&tree).cells();
+        let mut cells = hashlife.cells();
+        expected.sort_unstable();
+        cells.sort_unstable();
+        assert_eq!(hashlife.step, tree.step);
+        assert_eq!(cells, expected);
+
+        let exported = hashlife.to_tree();
+        assert_eq!(exported.step, tree.step);
+        for &(x, y) in &expected {
+            assert!(exported.get(x, y) > 0);
+        }
+    }
+}
+
+#[test]
+fn test_set_rule() {
+    let mut tree = RegionTree::new();
+    for x in 0..8 {
+        tree.insert(x, x % 3, 0);
+    }
+    let mut hashlife = HashLife::from_tree(&tree);
+    hashlife.step_by(64);
+    let mut restarted = HashLife::from_tree(&hashlife.to_tree());
+
+    // The results memoized for single rotation must not be reused for critters
+    let critters: BlockRule = "critters".parse().unwrap();
+    hashlife.set_rule(critters.clone());
+    restarted.set_rule(critters);
+    hashlife.step_by(64);
+    restarted.step_by(64);
+    let mut cells = hashlife.cells();
+    let mut expected = restarted.cells();
+    cells.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(cells, expected);
+    assert_eq!(hashlife.rule().name, "critters");
+}
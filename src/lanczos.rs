@@ -1,8 +1,8 @@
-use crate::regions::RegionTree;
+use crate::regions::{RegionTree, Topology};
 use std::collections::VecDeque;
 use std::time::Duration;
 
-pub const LANCZOS_WIDTH: f32 = 4.0;
+/// Floating-point type the interpolation is computed with
 pub type Prec = f64;
 
 /// The "sinc" mathematical function
@@ -31,21 +31,32 @@ pub fn lanczos_kernel(order: usize, points: usize) -> Vec<Prec> {
     res
 }
 
-/// A wrapper around RegionTree that interpolates the points based on the lanczos filter
+/// A wrapper around RegionTree that interpolates the points based on the lanczos filter.
+/// Only `interval` can be changed freely: the other parameters determine which states are buffered,
+/// so they are private and changed through methods such as `set_speed`, which rebuild the buffer
 pub struct LanczosInterpolator {
-    pub kernel: Vec<Prec>,
-    pub order: usize,
-    pub timesteps: usize,
-    pub time: f32,
-    pub smoothing: usize,
+    kernel: Vec<Prec>,
+    order: usize,
+    /// Number of points of the kernel between two states
+    timesteps: usize,
+    /// Time elapsed since the first buffered state was displayed, in states
+    time: f32,
+    smoothing: usize,
+    /// Number of milliseconds between two states
     pub interval: u32,
-    pub step_delta: usize,
+    step_delta: usize,
+    /// Whether the simulation is played backwards
+    reverse: bool,
 
-    pub states: VecDeque<Vec<(i64, i64)>>,
-    pub tree: RegionTree,
+    states: VecDeque<Vec<(i64, i64)>>,
+    /// Generation of each of `states`
+    generations: VecDeque<usize>,
+    tree: RegionTree,
 }
 
 impl LanczosInterpolator {
+    /// Creates an interpolator of order `order`, showing `step_delta` generations every `interval` milliseconds,
+    /// and spreading the interpolation over `smoothing` generations
     pub fn new(tree: RegionTree, order: usize, timesteps: usize, smoothing: usize, interval: u32, step_delta: usize) -> Self {
         Self {
             kernel: lanczos_kernel(order, timesteps * smoothing),
@@ -55,17 +66,26 @@ impl LanczosInterpolator {
             smoothing,
             interval,
             step_delta,
+            reverse: false,
 
             states: VecDeque::with_capacity(2 * order * smoothing),
+            generations: VecDeque::with_capacity(2 * order * smoothing),
             tree
         }
     }
 
+    /// Returns the simulation being interpolated, which is ahead of what is displayed
+    pub fn tree(&self) -> &RegionTree {
+        &self.tree
+    }
+
+    /// Returns the number of generations that have to be known ahead of what is displayed
     pub fn required_states(&self) -> usize {
         // self.kernel.len()
         2 * self.order * self.smoothing
     }
 
+    /// Advances the displayed time by `dt`, and returns the interpolated position of every particle
     pub fn get(&mut self, dt: Duration) -> Vec<(f32, f32)> {
         let dt = dt.as_millis() as f32 / self.interval as f32;
         self.time += dt;
@@ -73,16 +93,92 @@ impl LanczosInterpolator {
         while self.time.floor() >= 1.0 {
             self.time -= 1.0;
             self.states.pop_front();
+            self.generations.pop_front();
         }
 
+        self.fill_states();
+        self.interpolate()
+    }
+
+    /// Returns a copy of the simulation at the generation that is currently displayed
+    pub fn displayed_tree(&self) -> RegionTree {
+        let mut res = self.tree.clone();
+        seek(&mut res, self.displayed_step());
+        res
+    }
+
+    /// Applies `edit` to the generation that is currently displayed, then rebuilds the buffered states from it
+    pub fn edit<F: FnOnce(&mut RegionTree)>(&mut self, edit: F) {
+        let current = self.displayed_step();
+        seek(&mut self.tree, current);
+        edit(&mut self.tree);
+        self.set_speed(self.step_delta, self.reverse);
+    }
+
+    /// Simulates the states that are needed ahead of what is displayed
+    fn fill_states(&mut self) {
         while self.states.len() < self.required_states() {
             self.states.push_back(self.tree.cells.clone());
+            self.generations.push_back(self.tree.step);
             for _ in 0..self.step_delta {
-                self.tree.tick();
+                if self.reverse {
+                    self.tree.untick();
+                } else {
+                    self.tree.tick();
+                }
             }
         }
+    }
 
-        self.interpolate()
+    /// Returns the number of generations between two states
+    pub fn step_delta(&self) -> usize {
+        self.step_delta
+    }
+
+    /// Returns the number of generations the interpolation is spread over
+    pub fn smoothing(&self) -> usize {
+        self.smoothing
+    }
+
+    /// Returns whether the simulation is played backwards
+    pub fn is_reversed(&self) -> bool {
+        self.reverse
+    }
+
+    /// Returns the generation that is currently displayed, rounded to the closest known state
+    pub fn displayed_step(&self) -> usize {
+        // The kernel is centered `time` states after the one before the middle of the buffer
+        let index = (self.required_states() / 2).saturating_sub(1) + self.time.round() as usize;
+        self.generations.get(index).copied().unwrap_or(self.tree.step)
+    }
+
+    /// Changes the number of generations between two states and the direction of the simulation.
+    /// The buffered states are rebuilt around the generation that is currently displayed, so that the animation continues from it
+    pub fn set_speed(&mut self, step_delta: usize, reverse: bool) {
+        let current = self.displayed_step();
+        seek(&mut self.tree, current);
+
+        self.step_delta = step_delta.max(1);
+        self.reverse = reverse;
+        // Once the states are filled again with `time` at 0, the current generation will be right before the middle of the buffer
+        let before = (self.required_states() / 2).saturating_sub(1);
+        // The simulation can't be rewound past generation 0, so the states that would come before it are copies of the first one
+        let rewound = if reverse { before } else { before.min(current / self.step_delta) };
+        for _ in 0..rewound * self.step_delta {
+            if reverse {
+                self.tree.tick();
+            } else {
+                self.tree.untick();
+            }
+        }
+        self.states.clear();
+        self.generations.clear();
+        for _ in rewound..before {
+            self.states.push_back(self.tree.cells.clone());
+            self.generations.push_back(self.tree.step);
+        }
+        self.time = 0.0;
+        self.fill_states();
     }
 
     fn interpolate(&self) -> Vec<(f32, f32)> {
@@ -90,24 +186,126 @@ impl LanczosInterpolator {
         let offset_int = offset.floor() as usize;
         let offset_frac = offset.fract() as Prec;
 
-        if self.states.len() == 0 {
+        if self.states.is_empty() {
             return vec![]
         }
 
         let mut res = vec![(0.0, 0.0); self.states[0].len() - 1];
         let reference = &self.states[self.required_states() / 2];
 
+        // On a torus, particles crossing an edge are interpolated along the shortest path
+        let wrap = |delta: i64, size: Option<i64>| match size {
+            Some(size) => (delta + size / 2).rem_euclid(size) - size / 2,
+            None => delta,
+        };
+        let (width, height) = match self.tree.topology {
+            Topology::Torus { width, height } => (Some(width), Some(height)),
+            _ => (None, None),
+        };
+
         for (i, state) in self.states.iter().enumerate() {
             let k = (
                 self.kernel[i * self.timesteps + offset_int] * (1.0 - offset_frac)
                 + self.kernel[i * self.timesteps + offset_int + 1] * offset_frac
              ) / (self.smoothing as Prec);
             for (j, ((ref mut x, ref mut y), (sx, sy))) in res.iter_mut().zip(state.iter().skip(1)).enumerate() {
-                *x += (*sx - reference[j + 1].0) as Prec * k;
-                *y += (*sy - reference[j + 1].1) as Prec * k;
+                *x += wrap(*sx - reference[j + 1].0, width) as Prec * k;
+                *y += wrap(*sy - reference[j + 1].1, height) as Prec * k;
             }
         }
 
         res.into_iter().enumerate().map(|(i, (x, y))| (x as f32 + reference[i + 1].0 as f32, y as f32 + reference[i + 1].1 as f32)).collect()
     }
 }
+
+/// Runs `tree` forwards or backwards until `generation`
+fn seek(tree: &mut RegionTree, generation: usize) {
+    while tree.step > generation {
+        tree.untick();
+    }
+    while tree.step < generation {
+        tree.tick();
+    }
+}
+
+#[test]
+fn test_set_speed() {
+    let mut tree = RegionTree::new();
+    tree.insert(0, 0, 0);
+    tree.insert(0, 1, 0);
+    tree.insert(0, 3, 0);
+    tree.insert(0, 4, 0);
+    for _ in 0..100 {
+        tree.tick();
+    }
+
+    let mut interpolator = LanczosInterpolator::new(tree, 3, 1, 4, 10, 1);
+    interpolator.set_speed(1, false);
+    interpolator.get(Duration::from_millis(0));
+    assert_eq!(interpolator.displayed_step(), 100);
+    for _ in 0..20 {
+        interpolator.get(Duration::from_millis(10));
+    }
+    assert_eq!(interpolator.displayed_step(), 120);
+    let before = interpolator.get(Duration::from_millis(0));
+
+    // Changing the direction or the speed continues from the same generation
+    for &(step_delta, reverse) in &[(1, true), (4, true), (2, false)] {
+        interpolator.set_speed(step_delta, reverse);
+        let after = interpolator.get(Duration::from_millis(0));
+        assert_eq!(interpolator.displayed_step(), 120);
+        for (a, b) in before.iter().zip(after.iter()) {
+            assert!((a.0 - b.0).abs() < 1.0 && (a.1 - b.1).abs() < 1.0, "{:?} != {:?}", before, after);
+        }
+    }
+
+    interpolator.set_speed(1, true);
+    for _ in 0..20 {
+        interpolator.get(Duration::from_millis(10));
+    }
+    assert_eq!(interpolator.displayed_step(), 100);
+
+    // Edits apply to the displayed generation
+    interpolator.edit(|tree| tree.insert(50, 50, 2));
+    assert_eq!(interpolator.displayed_step(), 100);
+    let tree = interpolator.displayed_tree();
+    assert_eq!(tree.step, 100);
+    assert_eq!(tree.population(), 5);
+    assert!(tree.get(50, 50) > 0);
+}
+
+#[test]
+fn test_set_speed_at_start() {
+    let mut tree = RegionTree::new();
+    tree.insert(0, 0, 0);
+    tree.insert(0, 1, 0);
+    tree.insert(0, 3, 0);
+    tree.insert(0, 4, 0);
+
+    // The first generation is shown first, even though there is nothing before it to interpolate with
+    let mut interpolator = LanczosInterpolator::new(tree.clone(), 3, 1, 4, 10, 1);
+    interpolator.set_speed(1, false);
+    let positions = interpolator.get(Duration::from_millis(0));
+    assert_eq!(interpolator.displayed_step(), 0);
+    for (&(x, y), (_, (cx, cy), _)) in positions.iter().zip(tree.particles()) {
+        assert!((x - cx as f32).abs() < 0.5 && (y - cy as f32).abs() < 0.5, "{:?}", positions);
+    }
+    for _ in 0..5 {
+        interpolator.get(Duration::from_millis(10));
+    }
+    assert_eq!(interpolator.displayed_step(), 5);
+
+    // Close to generation 0, the states that can be rewound to keep their spacing
+    interpolator.set_speed(2, false);
+    interpolator.get(Duration::from_millis(0));
+    assert_eq!(interpolator.displayed_step(), 5);
+    interpolator.get(Duration::from_millis(10));
+    assert_eq!(interpolator.displayed_step(), 7);
+
+    let mut interpolator = LanczosInterpolator::new(tree, 3, 1, 4, 10, 4);
+    interpolator.set_speed(4, false);
+    interpolator.get(Duration::from_millis(0));
+    assert_eq!(interpolator.displayed_step(), 0);
+    interpolator.get(Duration::from_millis(10));
+    assert_eq!(interpolator.displayed_step(), 4);
+}
@@ -0,0 +1,47 @@
+//! A simulator for the single rotation rule and other reversible, particle-conserving rules on the Margolus neighborhood.
+//!
+//! The main engine is `RegionTree`, which tracks the identity and color of every particle.
+//! `HashLife` and `PackedTree` are faster engines which only know whether each cell is alive.
+//! Patterns are read and written with the `rle` and `snapshot` modules, and the `analysis`, `capture`, `census`,
+//! `search` and `collide` modules look for and identify the objects that they contain.
+
+#![warn(missing_docs)]
+
+/// The `RegionTree` engine and the shape of its universe
+pub mod regions;
+/// Block rules, as lookup tables of permutations
+pub mod rules;
+
+/// Rendering of a simulation into a window
+pub mod display;
+/// The interactive window
+pub mod viewer;
+/// Offscreen rendering into PNG images and animated GIFs
+pub mod export;
+/// Vector export of a generation and of the paths of its particles
+pub mod svg;
+/// Smooth interpolation of the positions of the particles between generations
+pub mod lanczos;
+/// Extraction of objects from a universe, and their canonical codes
+pub mod capture;
+/// A memoized engine, for skipping ahead by many generations
+pub mod hashlife;
+/// A bit-packed engine, without particle tracking
+pub mod packed;
+/// Period and velocity detection
+pub mod analysis;
+/// Identification and counting of the objects of a universe
+pub mod census;
+/// Random soup search
+pub mod search;
+/// Enumeration of the collisions between two ships
+pub mod collide;
+/// Reading and writing of patterns in the RLE format
+pub mod rle;
+/// Non-interactive runs, until a generation or until a condition holds
+pub mod batch;
+/// Binary snapshots of a `RegionTree`, for saving and resuming long runs
+pub mod snapshot;
+
+pub use regions::{Region, RegionTree, Topology, REGION_SIZE};
+pub use rules::BlockRule;
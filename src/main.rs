@@ -1,46 +1,103 @@
+use std::io::Write;
+use std::path::Path;
 use std::time::{Duration, Instant};
-use std::io::BufRead;
-use std::str::FromStr;
-// use rand::Rng;
 
-pub mod regions;
-use regions::*;
+use single_rotation::*;
 
-pub mod display;
-pub mod lanczos;
-pub mod capture;
+mod cli;
+use cli::{Args, Command};
 
 fn main() {
-    let graphical = std::env::args().any(|arg| arg == "-g");
-    let mut tree = RegionTree::new();
-    let mut steps: usize = 1;
-    let mut interval: u32 = 100;
-    let mut smoothing: usize = 4;
-    parse_rle(&mut tree, &mut steps, &mut interval, &mut smoothing);
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\nRun `single-rotation help` for the list of commands and options", err);
+            std::process::exit(2);
+        }
+    };
 
-    let fps = 1000 / interval;
+    let res = match args.command {
+        Command::Run => run(&args),
+        Command::View => view(&args),
+        Command::Render => render(&args),
+        Command::Census => census(&args),
+        Command::Analyse => analyse(&args),
+        Command::Search => search(&args),
+        Command::Collide => collide(&args),
+        Command::Convert => convert(&args),
+        Command::Help => {
+            print!("{}", cli::usage());
+            Ok(())
+        }
+    };
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
 
-    // let mut rng = rand::thread_rng();
-    let mut total_duration = Duration::new(0, 0);
+/// Runs the pattern without displaying it, then writes the final pattern.
+/// Exits with the status 3 if `--stop-when` didn't hold before the last generation
+fn run(args: &Args) -> Result<(), String> {
+    let (mut tree, settings) = load_pattern(args)?;
+    let defaults = batch::BatchConfig::default();
+    let config = batch::BatchConfig {
+        until: target_generation(args, &tree)?,
+        stop_when: args.value("--stop-when")?,
+        check_interval: args.value("--check-interval")?.unwrap_or(defaults.check_interval),
+        max_period: args.value("--max-period")?.unwrap_or(defaults.max_period),
+        engine: engine(args, &tree)?,
+    };
+    if config.until.is_none() && config.stop_when.is_none() {
+        return Err(String::from("run needs --generations, --until or --stop-when"))
+    }
 
-    if graphical {
-        let mut window = display::spawn();
-        let mut interpolator = lanczos::LanczosInterpolator::new(tree, 3, (50 / fps as usize).max(1), smoothing, interval, steps);
-        println!("");
-        let mut previous_time = Instant::now();
-        while window.is_open() && !window.is_key_down(minifb::Key::Escape) {
-            let start = Instant::now();
-            display::draw(&mut window, &mut interpolator, previous_time.elapsed());
-            previous_time = start;
-            print!("\x1b[1F");
-            println!("Step: {}", interpolator.tree.step);
-            if let Some(duration) = Duration::new(0, 20_000_000).checked_sub(start.elapsed()) {
-                std::thread::sleep(duration);
-            }
+    let mut checkpointer = checkpointer(args, &tree, config.until)?;
+    let outcome = batch::run(&mut tree, &config, checkpointer.as_mut())
+        .map_err(|err| format!("Couldn't save a snapshot: {}", err))?;
+    eprintln!("Generation {}: {} particles ({})", tree.step, tree.population(), outcome);
+
+    if !args.has("--quiet") {
+        let format = args.value::<String>("--to")?.unwrap_or_else(|| String::from("rle"));
+        let output = encode_pattern(&tree, settings, &format, config.max_period)?;
+        write_output(args.value::<String>("--output")?.as_deref(), &output)?;
+    }
+    if config.stop_when.is_some() && outcome == batch::BatchOutcome::Reached {
+        std::process::exit(3);
+    }
+    Ok(())
+}
+
+/// Displays the pattern in the terminal or in a window, forever
+fn view(args: &Args) -> Result<(), String> {
+    let (mut tree, settings) = load_pattern(args)?;
+    let steps = args.value("--steps")?.unwrap_or(settings.steps);
+    let interval = args.value::<u32>("--interval")?.unwrap_or(settings.interval).max(1);
+    let smoothing = args.value("--smoothing")?.unwrap_or(settings.smoothing);
+
+    if args.has("--window") {
+        if args.has("--checkpoint") || args.has("--checkpoint-interval") {
+            return Err(String::from("--checkpoint and --checkpoint-interval can't be used with --window"))
+        }
+        let mut viewer = viewer::Viewer::new(tree, rle::RleSettings { steps, interval, smoothing });
+        if let Some(path) = args.value::<String>("--stamp")? {
+            viewer.stamp = Some(read_pattern(&path, rle_mode(args))?.0);
+        }
+        viewer.output = args.value("--output")?;
+        if args.has("--follow") {
+            viewer.tracking = Some(viewer::Tracking::Universe);
         }
-        return
+        if let Some(length) = args.value("--trails")? {
+            viewer.trails.style.length = length;
+            viewer.show_trails = true;
+        }
+        viewer.run();
+        return Ok(())
     }
 
+    let mut checkpointer = checkpointer(args, &tree, None)?;
+    let mut total_duration = Duration::new(0, 0);
+
     loop {
         // let offset = (tree.step as i64 / 12) * 2;
 
@@ -48,8 +105,10 @@ fn main() {
             // for x in (offset - 4)..=(offset + 4) {
             for x in -4..=96 {
                 let n = tree.get(x, y);
-                if n > 0 {
-                    if tree.cells.len() <= 10 {
+                if tree.topology().normalize(x, y) != Some((x, y)) {
+                    print!(" ");
+                } else if n > 0 {
+                    if tree.max_id() < 10 {
                         print!("{}", n);
                     } else {
                         print!("#");
@@ -58,15 +117,14 @@ fn main() {
                     print!("·");
                 }
             }
-            print!("\n");
+            println!();
         }
 
         println!("Step: {}", tree.step);
 
         let start = Instant::now();
-        for _ in 0..steps {
-            tree.tick();
-        }
+        let generation = tree.step + steps;
+        run_until(&mut tree, generation, &mut checkpointer)?;
         total_duration += start.elapsed();
         let sps = (tree.step as f64 / total_duration.as_micros() as f64) * 1.0e6;
         print!("\x1b[0K");
@@ -78,125 +136,282 @@ fn main() {
     }
 }
 
-fn parse_rle(tree: &mut RegionTree, steps: &mut usize, interval: &mut u32, smoothing: &mut usize) {
-    let mut x = 0;
-    let mut sx = 0;
-    let mut y = 0;
-    let mut color = 0;
-    while let Some(Ok(rle)) = std::io::stdin().lock().lines().next() {
-        let mut count = String::new();
-        let mut input_x = false;
-        let mut input_y = false;
-        let mut input_steps = false;
-        let mut input_interval = false;
-        let mut input_smoothing = false;
-        let mut sign = 1;
-        for c in rle.chars() {
-            if c == 'x' {
-                input_x = true;
-            } else if c == 'y' {
-                input_y = true;
-            } else if c == 's' {
-                input_steps = true;
-            } else if c == 'i' {
-                input_interval = true;
-            } else if c == 'u' {
-                input_smoothing = true;
-            } else if c >= '0' && c <= '9' {
-                count.push(c);
-            } else if c == '-' {
-                sign = -1;
-            } else if input_x {
-                if count.len() > 0 {
-                    sx = count.parse::<i64>().unwrap() * sign as i64;
-                    x = sx;
-                    sign = 1;
-                    count = String::new();
-                    input_x = false;
-                }
-            } else if input_y {
-                if count.len() > 0 {
-                    y = count.parse::<i64>().unwrap() * sign as i64;
-                    sign = 1;
-                    count = String::new();
-                    input_y = false;
-                }
-            } else if input_steps {
-                if count.len() > 0 {
-                    *steps = count.parse::<usize>().unwrap();
-                    count = String::new();
-                    input_steps = false;
-                }
-            } else if input_interval {
-                if count.len() > 0 {
-                    *interval = count.parse::<u32>().unwrap();
-                    count = String::new();
-                    input_interval = false;
-                }
-            } else if input_smoothing {
-                if count.len() > 0 {
-                    *smoothing = count.parse::<usize>().unwrap();
-                    count = String::new();
-                    input_smoothing = false;
-                }
-            } else if c == 'o' {
-                if count.len() > 0 {
-                    for _ in 0..usize::from_str(&count).unwrap() {
-                        tree.insert(x, y, color);
-                        x += 1;
-                    }
-                    count = String::new();
-                } else {
-                    tree.insert(x, y, color);
-                    x += 1;
-                }
-            } else if c == 'b' {
-                if count.len() > 0 {
-                    x += count.parse::<i64>().unwrap();
-                    count = String::new();
-                } else {
-                    x += 1;
-                }
-            } else if c == '$' {
-                if count.len() > 0 {
-                    y += count.parse::<i64>().unwrap();
-                    x = sx;
-                    count = String::new();
-                } else {
-                    y += 1;
-                    x = sx;
-                }
-            } else if c == 'c' {
-                if count.len() > 0 {
-                    color = count.parse::<usize>().unwrap();
-                    count = String::new();
-                } else {
-                    color = 0;
-                }
-            } else if c == '!' {
-                return
-            }
+/// Renders the pattern into a PNG image, or into an animated GIF or an SVG image if the output ends with `.gif` or `.svg`
+fn render(args: &Args) -> Result<(), String> {
+    let (mut tree, settings) = load_pattern(args)?;
+    let output = args.value::<String>("--output")?.ok_or("render needs an --output file")?;
+    let extension = Path::new(&output).extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    let animated = extension == "gif";
+    let frames = args.value::<usize>("--frames")?;
+    if !animated && frames.unwrap_or(1) != 1 {
+        return Err(String::from("PNG and SVG images hold a single frame; use a .gif output for animations"))
+    }
+    if extension != "svg" && (args.has("--grid") || args.has("--trajectories")) {
+        return Err(String::from("--grid and --trajectories need an .svg output"))
+    }
+    if extension == "svg" && args.has("--no-fit") {
+        return Err(String::from("SVG images are always framed on the particles, so --no-fit needs a .png or .gif output"))
+    }
+
+    let defaults = export::AnimationConfig::default();
+    let config = export::AnimationConfig {
+        width: args.value("--width")?.unwrap_or(defaults.width),
+        height: args.value("--height")?.unwrap_or(defaults.height),
+        frames: frames.unwrap_or(if animated { 100 } else { 1 }),
+        steps: args.value("--steps")?.unwrap_or(settings.steps),
+        smoothing: args.value("--smoothing")?,
+        subframes: args.value("--subframes")?.unwrap_or(defaults.subframes),
+        camera: if args.has("--no-fit") { Some(display::Camera::default()) } else { None },
+    };
+    if let Some(generation) = target_generation(args, &tree)? {
+        run_until(&mut tree, generation, &mut None)?;
+    }
+
+    if extension == "svg" {
+        let config = svg::SvgConfig {
+            grid: args.has("--grid"),
+            trajectories: args.value("--trajectories")?.unwrap_or(0),
+            ..svg::SvgConfig::default()
+        };
+        let mut res = Vec::new();
+        svg::write_svg(&tree, &config, &mut res).expect("writing to a Vec can't fail");
+        return write_output(Some(&output), &res)
+    }
+
+    let res = if animated {
+        let interval = args.value::<u32>("--interval")?.unwrap_or(settings.interval);
+        std::fs::File::create(&output)
+            .and_then(|file| export::write_gif(tree, &config, interval, std::io::BufWriter::new(file)))
+            .map(|_| ())
+    } else {
+        export::write_png(tree, &config, Path::new(&output))
+    };
+    res.map_err(|err| format!("Couldn't write {}: {}", output, err))
+}
+
+/// Runs the pattern, then counts the objects it is made of
+fn census(args: &Args) -> Result<(), String> {
+    let (mut tree, _) = load_pattern(args)?;
+    let max_period = args.value("--max-period")?.unwrap_or(1000);
+    if let Some(generation) = target_generation(args, &tree)? {
+        let config = batch::BatchConfig {
+            until: Some(generation),
+            engine: engine(args, &tree)?,
+            ..batch::BatchConfig::default()
+        };
+        let mut checkpointer = checkpointer(args, &tree, Some(generation))?;
+        batch::run(&mut tree, &config, checkpointer.as_mut()).map_err(|err| format!("Couldn't save a snapshot: {}", err))?;
+    }
+    print!("{}", census::census(&tree, max_period));
+    Ok(())
+}
+
+/// Tells whether the pattern is an oscillator or a spaceship
+fn analyse(args: &Args) -> Result<(), String> {
+    let (tree, _) = load_pattern(args)?;
+    println!("{}", analysis::classify(&tree, args.value("--max-period")?.unwrap_or(10000)));
+    Ok(())
+}
+
+/// Runs random soups
+fn search(args: &Args) -> Result<(), String> {
+    let count = match args.positional.first() {
+        Some(count) => count.parse::<usize>().map_err(|err| format!("invalid number of soups {:?}: {}", count, err))?,
+        None => 100,
+    };
+    let defaults = search::SearchConfig::default();
+    let config = search::SearchConfig {
+        rule: args.value("--rule")?.unwrap_or(defaults.rule),
+        size: args.value("--size")?.unwrap_or(defaults.size),
+        density: args.value("--density")?.unwrap_or(defaults.density),
+        symmetry: args.value("--symmetry")?.unwrap_or(defaults.symmetry),
+        max_generations: args.value("--max-generations")?.unwrap_or(defaults.max_generations),
+        check_interval: defaults.check_interval,
+        max_period: args.value("--max-period")?.unwrap_or(defaults.max_period),
+    };
+    if !(0.0..=1.0).contains(&config.density) {
+        return Err(format!("--density must be between 0 and 1, got {}", config.density))
+    }
+    let seed = args.value("--seed")?.unwrap_or(0);
+    if let Some(path) = args.value::<String>("--emit-soup")? {
+        let output = encode_pattern(&search::soup(&config, seed), rle::RleSettings::default(), "rle", config.max_period)?;
+        return write_output(Some(&path), &output)
+    }
+    print!("{}", search::search(&config, seed, count));
+    Ok(())
+}
+
+/// Collides two ships in every possible way
+fn collide(args: &Args) -> Result<(), String> {
+    let (a, b) = match args.positional.as_slice() {
+        [a, b] => (a, b),
+        _ => return Err(String::from("collide needs the codes of two ships")),
+    };
+    let rule: rules::BlockRule = args.value("--rule")?.unwrap_or_default();
+    let defaults = collide::CollisionConfig::default();
+    let config = collide::CollisionConfig {
+        range: args.value("--range")?.unwrap_or(defaults.range),
+        max_generations: args.value("--max-generations")?.unwrap_or(defaults.max_generations),
+        max_period: args.value("--max-period")?.unwrap_or(defaults.max_period),
+        ..defaults
+    };
+    let a = capture::decode(a, rule.clone()).map_err(|err| format!("Invalid code {}: {}", a, err))?;
+    let b = capture::decode(b, rule).map_err(|err| format!("Invalid code {}: {}", b, err))?;
+    println!("{}", collide::TABLE_HEADER);
+    for collision in collide::collide(&a, &b, &config) {
+        println!("{}", collision);
+    }
+    Ok(())
+}
+
+/// Writes the pattern in another format
+fn convert(args: &Args) -> Result<(), String> {
+    let (tree, settings) = load_pattern(args)?;
+    let format = args.value::<String>("--to")?.unwrap_or_else(|| String::from("rle"));
+    let output = encode_pattern(&tree, settings, &format, args.value("--max-period")?.unwrap_or(1000))?;
+    write_output(args.positional.get(1).map(String::as_str), &output)
+}
+
+/// Returns `tree` in `format`: `rle`, `standard`, `snapshot`, `cells` or `code`.
+/// Codes can only be given to patterns which are periodic within `max_period` generations
+fn encode_pattern(tree: &RegionTree, settings: rle::RleSettings, format: &str, max_period: usize) -> Result<Vec<u8>, String> {
+    let mut output = Vec::new();
+    match format {
+        "rle" | "standard" => {
+            let mut writer = rle::RleWriter::new(if format == "rle" { rle::RleMode::Legacy } else { rle::RleMode::Standard });
+            writer.settings = settings;
+            writer.write(tree, &mut output).expect("writing to a Vec can't fail");
         }
-        if input_x {
-            if count.len() > 0 {
-                x = count.parse::<i64>().unwrap();
-            }
-        } else if input_y {
-            if count.len() > 0 {
-                y = count.parse::<i64>().unwrap();
+        "snapshot" => snapshot::save(tree, &mut output).expect("writing to a Vec can't fail"),
+        "cells" => {
+            for (_, (x, y), color) in tree.particles() {
+                output.extend_from_slice(format!("{} {} {}\n", x, y, color).as_bytes());
             }
-        } else if input_steps {
-            if count.len() > 0 {
-                *steps = count.parse::<usize>().unwrap();
-            }
-        } else if input_interval {
-            if count.len() > 0 {
-                *interval = count.parse::<u32>().unwrap();
+        }
+        "code" => match analysis::classify(tree, max_period) {
+            analysis::Classification::Oscillator(periodicity) | analysis::Classification::Spaceship(periodicity) => {
+                output.extend_from_slice(capture::encode(tree, &periodicity).as_bytes());
+                output.push(b'\n');
             }
-        } else if input_smoothing {
-            if count.len() > 0 {
-                *smoothing = count.parse::<usize>().unwrap();
+            _ => return Err(format!("The pattern isn't periodic within {} generations, so it has no code", max_period)),
+        },
+        _ => return Err(format!("Unknown format {}; expected rle, standard, snapshot, cells or code", format)),
+    }
+    Ok(output)
+}
+
+/// Writes `output` into the file at `path`, or to stdout if there is none or if it is `-`
+fn write_output(path: Option<&str>, output: &[u8]) -> Result<(), String> {
+    match path {
+        None | Some("-") => std::io::stdout().write_all(output).map_err(|err| format!("Couldn't write the pattern: {}", err)),
+        Some(path) => std::fs::write(path, output).map_err(|err| format!("Couldn't write {}: {}", path, err)),
+    }
+}
+
+/// Reads the pattern from the file given as first argument, from stdin if there is none, or from the latest snapshot of `--resume`,
+/// then applies `--rule`, `--jump` and `--threads`
+fn load_pattern(args: &Args) -> Result<(RegionTree, rle::RleSettings), String> {
+    let mode = rle_mode(args);
+    let resume = args.value::<String>("--resume")?;
+    let (mut tree, settings) = if let Some(directory) = &resume {
+        let path = snapshot::latest(Path::new(directory))
+            .map_err(|err| format!("Couldn't read {}: {}", directory, err))?
+            .ok_or_else(|| format!("No snapshot found in {}", directory))?;
+        let tree = snapshot::load_file(&path).map_err(|err| format!("Couldn't load {}: {}", path.display(), err))?;
+        (tree, rle::RleSettings::default())
+    } else {
+        match args.positional.first().map(String::as_str) {
+            None | Some("-") => {
+                rle::read(std::io::stdin().lock(), mode).map_err(|err| format!("Couldn't read the pattern: {}", err))?
             }
+            Some(path) => read_pattern(path, mode)?,
+        }
+    };
+
+    if let Some(rule) = args.value("--rule")? {
+        tree.set_rule(rule);
+    }
+    // Resumed snapshots were taken after the jump
+    if let Some(generations) = args.value("--jump")?.filter(|_| resume.is_none()) {
+        let mut hashlife = hashlife::HashLife::from_tree(&tree);
+        hashlife.step_by(generations);
+        tree = hashlife.to_tree();
+    }
+    tree.threads = args.value("--threads")?.unwrap_or(1);
+
+    Ok((tree, settings))
+}
+
+/// Returns the RLE dialect given by `--standard`
+fn rle_mode(args: &Args) -> rle::RleMode {
+    if args.has("--standard") {
+        rle::RleMode::Standard
+    } else {
+        rle::RleMode::Legacy
+    }
+}
+
+/// Reads the RLE file or snapshot at `path`
+fn read_pattern(path: &str, mode: rle::RleMode) -> Result<(RegionTree, rle::RleSettings), String> {
+    let bytes = std::fs::read(path).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+    if snapshot::is_snapshot(&bytes) {
+        let tree = snapshot::load(&mut bytes.as_slice()).map_err(|err| format!("Couldn't load {}: {}", path, err))?;
+        Ok((tree, rle::RleSettings::default()))
+    } else {
+        let input = String::from_utf8(bytes).map_err(|_| format!("{} is neither an RLE file nor a snapshot", path))?;
+        rle::parse(&input, mode).map_err(|err| format!("Couldn't read {}: {}", path, err))
+    }
+}
+
+/// Returns the generation to run until, from either `--generations`, counted from the generation of the pattern, or `--until`.
+/// Resumed runs stop at the generation recorded by the run they continue, unless one of them is given
+fn target_generation(args: &Args, tree: &RegionTree) -> Result<Option<usize>, String> {
+    match (args.value::<usize>("--generations")?, args.value::<usize>("--until")?) {
+        (Some(_), Some(_)) => Err(String::from("--generations and --until can't be used together")),
+        (_, Some(until)) => Ok(Some(until)),
+        (Some(generations), None) => Ok(Some(tree.step + generations)),
+        (None, None) => match args.value::<String>("--resume")? {
+            Some(directory) => snapshot::load_target(Path::new(&directory)).map_err(|err| format!("Couldn't read {}: {}", directory, err)),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Returns the engine of `--engine`; the packed engine can't simulate bounded universes
+fn engine(args: &Args, tree: &RegionTree) -> Result<batch::Engine, String> {
+    let engine = args.value("--engine")?.unwrap_or_default();
+    if engine == batch::Engine::Packed && tree.topology().size().is_some() {
+        return Err(String::from("The packed engine only simulates unbounded universes"))
+    }
+    Ok(engine)
+}
+
+/// Returns the checkpointer of `--checkpoint`, or of `--resume` so that resumed runs keep being saved.
+/// New checkpoint directories record `target`, the generation the run stops at, and so do resumed ones when it was given explicitly
+fn checkpointer(args: &Args, tree: &RegionTree, target: Option<usize>) -> Result<Option<snapshot::Checkpointer>, String> {
+    let (directory, resumed) = match args.value::<String>("--checkpoint")? {
+        Some(directory) => (directory, false),
+        None => match args.value::<String>("--resume")? {
+            Some(directory) => (directory, true),
+            None => return Ok(None),
+        },
+    };
+    let interval = args.value("--checkpoint-interval")?.unwrap_or(100_000);
+    let checkpointer = snapshot::Checkpointer::new(&directory, interval, tree.step)
+        .map_err(|err| format!("Couldn't create {}: {}", directory, err))?;
+    if !resumed || args.has("--generations") || args.has("--until") {
+        snapshot::save_target(Path::new(&directory), target).map_err(|err| format!("Couldn't write into {}: {}", directory, err))?;
+    }
+    Ok(Some(checkpointer))
+}
+
+/// Runs `tree` until `generation`, saving snapshots along the way
+fn run_until(tree: &mut RegionTree, generation: usize, checkpointer: &mut Option<snapshot::Checkpointer>) -> Result<(), String> {
+    while tree.step < generation {
+        tree.tick();
+        if let Some(checkpointer) = checkpointer {
+            checkpointer.update(tree).map_err(|err| format!("Couldn't save a snapshot: {}", err))?;
         }
     }
+    Ok(())
 }
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use super::regions::{nearest_region, RegionTree, REGION_SIZE};
+use super::rules::{BlockRule, Permutation, permute_mask};
+
+/// Same as REGION_SIZE, converted to i64
+const R: i64 = REGION_SIZE as i64;
+
+/// A 16x16 region, as four words of four rows each: row `y` is stored in bits `16 * (y % 4)` to `16 * (y % 4) + 15`
+/// of word `y / 4`, with bit `x` of each row being the cell in column `x`
+pub type PackedRegion = [u64; 4];
+
+/// Selects the even rows of each word
+const TOP_ROWS: u64 = 0x0000_ffff_0000_ffff;
+/// Selects the even columns of the even rows of each word
+const LEFT_CELLS: u64 = 0x0000_5555_0000_5555;
+
+/// A universe storing only whether or not each cell is alive, 1 bit per cell.
+/// Regions are stepped all at once using bitwise operations; use `RegionTree` to track the identity of the particles.
+///
+/// The squares of the even phase lie within a single region. On odd phases, each square is looked up in a window
+/// holding the cells from `(x + 1, y + 1)` to `(x + 16, y + 16)` of the region at `(x, y)`, which is gathered from that region
+/// and its right, bottom and bottom-right neighbors, then written back into them.
+#[derive(Debug, Clone)]
+pub struct PackedTree {
+    pub(crate) regions: HashMap<(i64, i64), PackedRegion>,
+    /// Current generation
+    pub step: usize,
+    pub(crate) rule: BlockRule,
+    /// Tables of `rule.inverse()`, used by `untick`
+    inverse: [[Permutation; 16]; 2],
+}
+
+impl PackedTree {
+    /// Creates a new, empty `PackedTree`, following `rule`
+    pub fn new(rule: BlockRule) -> Self {
+        Self {
+            regions: HashMap::new(),
+            step: 0,
+            inverse: rule.inverse().table,
+            rule,
+        }
+    }
+
+    /// Returns the rule the universe follows; use `set_rule` to change it
+    pub fn rule(&self) -> &BlockRule {
+        &self.rule
+    }
+
+    /// Changes the rule the universe follows from the current generation on
+    pub fn set_rule(&mut self, rule: BlockRule) {
+        self.inverse = rule.inverse().table;
+        self.rule = rule;
+    }
+
+    /// Imports the cells, rule and step of a `RegionTree`.
+    /// Its topology is ignored, as this engine always simulates an unbounded plane
+    pub fn from_tree(tree: &RegionTree) -> Self {
+        let mut res = Self::new(tree.rule.clone());
+        res.step = tree.step;
+        for region in tree.regions.iter().filter(|region| region.n_cells > 0) {
+            for (y, row) in region.cells.iter().enumerate() {
+                for (x, &cell) in row.iter().enumerate() {
+                    if cell > 0 {
+                        res.insert(region.x + x as i64, region.y + y as i64);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Exports the universe into a new `RegionTree`; every particle is given the color `0`
+    pub fn to_tree(&self) -> RegionTree {
+        let mut tree = RegionTree::with_rule(self.rule.clone());
+        tree.insert_all(self.cells().into_iter().map(|position| (position, 0)));
+        tree.step = self.step;
+        tree
+    }
+
+    /// Returns the region holding `x`, `y` and the coordinates of that cell within it
+    fn locate(&self, x: i64, y: i64) -> ((i64, i64), usize, usize) {
+        let (rx, ry) = nearest_region(x, y);
+        ((rx, ry), (x - rx) as usize, (y - ry) as usize)
+    }
+
+    /// Gets the cell at `x`, `y`
+    pub fn get(&self, x: i64, y: i64) -> bool {
+        let (key, x, y) = self.locate(x, y);
+        match self.regions.get(&key) {
+            Some(region) => (region[y / 4] >> (16 * (y % 4) + x)) & 1 == 1,
+            None => false,
+        }
+    }
+
+    /// Inserts a cell at `x`, `y`
+    pub fn insert(&mut self, x: i64, y: i64) {
+        let (key, x, y) = self.locate(x, y);
+        self.regions.entry(key).or_insert([0; 4])[y / 4] |= 1 << (16 * (y % 4) + x);
+    }
+
+    /// Returns the number of live cells
+    pub fn population(&self) -> usize {
+        self.regions.values().flat_map(|region| region.iter()).map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Returns the coordinates of all of the live cells
+    pub fn cells(&self) -> Vec<(i64, i64)> {
+        let mut res = Vec::new();
+        for (&(rx, ry), region) in self.regions.iter() {
+            for (w, &word) in region.iter().enumerate() {
+                let mut word = word;
+                while word != 0 {
+                    let bit = word.trailing_zeros() as i64;
+                    word &= word - 1;
+                    res.push((rx + bit % 16, ry + 4 * w as i64 + bit / 16));
+                }
+            }
+        }
+        res
+    }
+
+    /// Steps the simulation forward by one generation
+    pub fn tick(&mut self) {
+        let phase = self.step % 2;
+        let table = self.rule.table[phase];
+        self.update(&table, phase);
+        self.step += 1;
+    }
+
+    /// Steps the simulation backward by one generation, undoing the last `tick`.
+    /// Does nothing if the simulation is at step 0.
+    pub fn untick(&mut self) {
+        if self.step == 0 {
+            return
+        }
+        self.step -= 1;
+        let phase = self.step % 2;
+        let table = self.inverse[phase];
+        self.update(&table, phase);
+    }
+
+    /// Applies `table` to every square of the partition of phase `phase`
+    fn update(&mut self, table: &[Permutation; 16], phase: usize) {
+        // Only the squares whose content changes need to be looked at
+        let changes = (0..16u8)
+            .map(|mask| (mask, permute_mask(mask, table[mask as usize])))
+            .filter(|&(mask, output)| mask != output)
+            .collect::<Vec<_>>();
+
+        if phase == 0 {
+            for region in self.regions.values_mut() {
+                for word in region.iter_mut() {
+                    if *word != 0 {
+                        *word = update_word(*word, &changes);
+                    }
+                }
+            }
+        } else {
+            self.update_odd(&changes);
+        }
+        self.regions.retain(|_, region| region.iter().any(|&word| word != 0));
+    }
+
+    /// Applies `changes` to the squares of the odd phase, which straddle the right and bottom edges of the regions.
+    /// The windows don't overlap, so each one is written back into the regions before the next one is read
+    fn update_odd(&mut self, changes: &[(u8, u8)]) {
+        // The window of a region also covers the left column and the top row of its neighbors
+        let mut windows = Vec::with_capacity(self.regions.len() * 4);
+        for &(x, y) in self.regions.keys() {
+            windows.extend_from_slice(&[(x, y), (x - R, y), (x, y - R), (x - R, y - R)]);
+        }
+        windows.sort_unstable();
+        windows.dedup();
+
+        let empty = [0; 4];
+        for (x, y) in windows {
+            let keys = [(x, y), (x + R, y), (x, y + R), (x + R, y + R)];
+            let [own, right, bottom, diagonal] = keys.map(|key| *self.regions.get(&key).unwrap_or(&empty));
+
+            // Row `row` of the window is made of row `row + 1` of the regions, shifted left by one cell
+            let mut window = [0; 4];
+            for row in 0..REGION_SIZE {
+                let (source, source_right) = if row + 1 < REGION_SIZE {
+                    (get_row(&own, row + 1), get_row(&right, row + 1))
+                } else {
+                    (get_row(&bottom, 0), get_row(&diagonal, 0))
+                };
+                set_row(&mut window, row, (source >> 1) | ((source_right & 1) << 15));
+            }
+            if window == empty {
+                continue
+            }
+            for word in window.iter_mut() {
+                *word = update_word(*word, changes);
+            }
+
+            let mut regions = [own, right, bottom, diagonal];
+            for row in 0..REGION_SIZE {
+                let value = get_row(&window, row);
+                // The first 15 cells go back into `own` or `bottom`, and the last one into `right` or `diagonal`
+                let (first, last, source_row) = if row + 1 < REGION_SIZE { (0, 1, row + 1) } else { (2, 3, 0) };
+                let kept = get_row(&regions[first], source_row) & 1;
+                set_row(&mut regions[first], source_row, kept | (value << 1));
+                let kept = get_row(&regions[last], source_row) & !1;
+                set_row(&mut regions[last], source_row, kept | (value >> 15));
+            }
+            for (key, region) in keys.iter().zip(regions.iter()) {
+                if *region != empty || self.regions.contains_key(key) {
+                    self.regions.insert(*key, *region);
+                }
+            }
+        }
+    }
+}
+
+/// Reads row `row` of a region
+#[inline]
+fn get_row(region: &PackedRegion, row: usize) -> u16 {
+    (region[row / 4] >> (16 * (row % 4))) as u16
+}
+
+/// Overwrites row `row` of a region
+#[inline]
+fn set_row(region: &mut PackedRegion, row: usize, value: u16) {
+    let shift = 16 * (row % 4);
+    region[row / 4] = (region[row / 4] & !(0xffff << shift)) | ((value as u64) << shift);
+}
+
+/// Applies the changes of a rule to the 16 squares held in a word.
+/// Each square is split into four bit planes, one per slot, which are then looked up in parallel
+#[inline]
+fn update_word(word: u64, changes: &[(u8, u8)]) -> u64 {
+    let top = word & TOP_ROWS;
+    let bottom = (word >> 16) & TOP_ROWS;
+    let planes = [top & LEFT_CELLS, (top >> 1) & LEFT_CELLS, (bottom >> 1) & LEFT_CELLS, bottom & LEFT_CELLS];
+    let mut new_planes = planes;
+
+    for &(mask, output) in changes {
+        let mut selected = LEFT_CELLS;
+        for (i, &plane) in planes.iter().enumerate() {
+            selected &= if (mask >> i) & 1 == 1 { plane } else { !plane };
+        }
+        if selected == 0 {
+            continue
+        }
+        for (i, plane) in new_planes.iter_mut().enumerate() {
+            if (output >> i) & 1 == 1 {
+                *plane |= selected;
+            } else {
+                *plane &= !selected;
+            }
+        }
+    }
+
+    let top = new_planes[0] | (new_planes[1] << 1);
+    let bottom = new_planes[3] | (new_planes[2] << 1);
+    top | (bottom << 16)
+}
+
+#[test]
+fn test_packed_tree() {
+    for rule in &["single-rotation", "billiard-ball", "critters"] {
+        let mut tree = RegionTree::with_rule(rule.parse().unwrap());
+        let mut seed = 192837465u64;
+        for _ in 0..200 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            tree.insert((seed % 64) as i64 - 32, ((seed >> 32) % 64) as i64 - 32, 0);
+        }
+        let sorted_cells = |tree: &RegionTree| {
+            let mut cells = tree.particles().map(|(_, position, _)| position).collect::<Vec<_>>();
+            cells.sort_unstable();
+            cells
+        };
+        let initial = sorted_cells(&tree);
+        let mut packed = PackedTree::from_tree(&tree);
+        assert_eq!(packed.population(), initial.len());
+
+        for _ in 0..101 {
+            tree.tick();
+            packed.tick();
+        }
+        let mut cells = packed.cells();
+        cells.sort_unstable();
+        assert_eq!(cells, sorted_cells(&tree));
+        assert_eq!(sorted_cells(&packed.to_tree()), cells);
+
+        for _ in 0..101 {
+            packed.untick();
+        }
+        assert_eq!(packed.step, 0);
+        let mut cells = packed.cells();
+        cells.sort_unstable();
+        assert_eq!(cells, initial);
+    }
+}
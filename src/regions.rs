@@ -1,4 +1,7 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use super::rules::{BlockRule, Permutation, IDENTITY, permute};
 
 /// The width and height of a "region", or partition of the 2D plane
 pub const REGION_SIZE: usize = 16;
@@ -20,21 +23,129 @@ pub const NEIGHBORS: [(i64, i64); 8] = [
 /// A partition of the 2D plane: contains REGION_SIZE² tiles
 #[derive(Debug, Clone)]
 pub struct Region {
-    pub x: i64,
-    pub y: i64,
-    pub cells: [[usize; REGION_SIZE]; REGION_SIZE],
-    pub neighbors: [Option<usize>; 8],
-    pub n_cells: usize,
+    pub(crate) x: i64,
+    pub(crate) y: i64,
+    pub(crate) cells: [[usize; REGION_SIZE]; REGION_SIZE],
+    pub(crate) neighbors: [Option<usize>; 8],
+    pub(crate) n_cells: usize,
+}
+
+/// The shape of the universe simulated by a `RegionTree`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Topology {
+    /// An unbounded plane
+    #[default]
+    Plane,
+    /// A finite torus, whose cells lie between `(0, 0)` and `(width - 1, height - 1)`; both sides must be even
+    Torus {
+        /// Number of columns of the torus
+        width: i64,
+        /// Number of rows of the torus
+        height: i64,
+    },
+    /// A box with reflecting walls, whose cells lie between `(0, 0)` and `(width - 1, height - 1)`; both sides must be even.
+    /// On the odd phase, the two cells of each square crossing a wall are swapped, which bounces particles and ships back off it.
+    Box {
+        /// Number of columns inside the box
+        width: i64,
+        /// Number of rows inside the box
+        height: i64,
+    },
+}
+
+impl Topology {
+    /// Returns the width and height of the universe, if it is bounded
+    pub fn size(&self) -> Option<(i64, i64)> {
+        match *self {
+            Topology::Plane => None,
+            Topology::Torus { width, height } | Topology::Box { width, height } => Some((width, height)),
+        }
+    }
+
+    /// Maps `x`, `y` to the coordinates of the cell within the universe, or `None` if it lies outside of it
+    pub fn normalize(&self, x: i64, y: i64) -> Option<(i64, i64)> {
+        match *self {
+            Topology::Plane => Some((x, y)),
+            Topology::Torus { width, height } => Some((x.rem_euclid(width), y.rem_euclid(height))),
+            Topology::Box { width, height } => {
+                if x >= 0 && y >= 0 && x < width && y < height {
+                    Some((x, y))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Returns true if the 2x2 square whose top-left corner is `x`, `y` doesn't cross the edges of the universe
+    fn contains_square(&self, x: i64, y: i64) -> bool {
+        match self.size() {
+            Some((width, height)) => x >= 0 && y >= 0 && x + 1 < width && y + 1 < height,
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Topology::Plane => write!(f, "plane"),
+            Topology::Torus { width, height } => write!(f, "torus:{}x{}", width, height),
+            Topology::Box { width, height } => write!(f, "box:{}x{}", width, height),
+        }
+    }
+}
+
+impl FromStr for Topology {
+    type Err = String;
+
+    /// Parses `plane`, `torus:WIDTHxHEIGHT` or `box:WIDTHxHEIGHT`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        if s == "plane" {
+            return Ok(Topology::Plane)
+        }
+
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let size = parts.next().ok_or_else(|| format!("expected a size after {:?}", kind))?;
+        let mut size = size.splitn(2, 'x').map(|n| n.trim().parse::<i64>());
+        let (width, height) = match (size.next(), size.next()) {
+            (Some(Ok(width)), Some(Ok(height))) => (width, height),
+            _ => return Err(format!("invalid size in {:?}", s)),
+        };
+        if width <= 0 || height <= 0 || width % 2 != 0 || height % 2 != 0 {
+            return Err(format!("the size of the universe must be even, got {}x{}", width, height))
+        }
+
+        match kind {
+            "torus" => Ok(Topology::Torus { width, height }),
+            "box" => Ok(Topology::Box { width, height }),
+            _ => Err(format!("unknown topology {:?}", kind)),
+        }
+    }
 }
 
 /// Holds a grid of `Region`s and a list of cells
 #[derive(Debug, Clone)]
 pub struct RegionTree {
-    pub regions: Vec<Region>,
-    pub hashmap: HashMap<(i64, i64), usize>,
-    pub cells: Vec<(i64, i64)>,
-    pub colors: Vec<usize>,
+    pub(crate) regions: Vec<Region>,
+    pub(crate) hashmap: HashMap<(i64, i64), usize>,
+    /// Position of each particle, indexed by its id; id 0 is never used
+    pub(crate) cells: Vec<(i64, i64)>,
+    pub(crate) colors: Vec<usize>,
+    /// Whether or not each particle is still in the universe; removed particles keep their id until `compact` is called
+    pub(crate) alive: Vec<bool>,
+    /// Current generation; the phase of the partition is `step % 2`
     pub step: usize,
+    /// Rule the universe follows. It is a value rather than a type parameter of the tree, because rules are picked at runtime,
+    /// from `--rule` or from the header of a pattern, and custom rules are only known once their string is parsed
+    pub(crate) rule: BlockRule,
+    /// Tables of `rule.inverse()`, used by `untick`
+    inverse: [[Permutation; 16]; 2],
+    /// Number of threads used to step the simulation; 1 steps it on the current thread
+    pub threads: usize,
+    pub(crate) topology: Topology,
 }
 
 impl Region {
@@ -48,34 +159,122 @@ impl Region {
             n_cells: 0,
         }
     }
+
+    /// Coordinates of the top-left cell of the region
+    pub fn position(&self) -> (i64, i64) {
+        (self.x, self.y)
+    }
+
+    /// Ids of the particles in each cell of the region, indexed by `[y][x]`; empty cells hold 0
+    pub fn cells(&self) -> &[[usize; REGION_SIZE]; REGION_SIZE] {
+        &self.cells
+    }
+
+    /// Returns the number of particles in the region
+    pub fn population(&self) -> usize {
+        self.n_cells
+    }
+}
+
+impl Default for RegionTree {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RegionTree {
-    /// Creates a new, empty `RegionTree`
+    /// Creates a new, empty `RegionTree`, following the single rotation rule
     pub fn new() -> Self {
+        Self::with_rule(BlockRule::default())
+    }
+
+    /// Creates a new, empty `RegionTree`, following `rule`
+    pub fn with_rule(rule: BlockRule) -> Self {
         Self {
             regions: Vec::new(),
             hashmap: HashMap::new(),
             step: 0,
             cells: vec![(0, 0)],
             colors: vec![0],
+            alive: vec![false],
+            inverse: rule.inverse().table,
+            rule,
+            threads: 1,
+            topology: Topology::Plane,
         }
     }
 
+    /// Changes the topology of the universe; the cells lying outside of it are removed
+    /// and the ones of a torus are wrapped around.
+    ///
+    /// Panics if the sides of a bounded universe aren't positive and even, as the partition would otherwise not line up.
+    pub fn set_topology(&mut self, topology: Topology) {
+        if let Some((width, height)) = topology.size() {
+            assert!(
+                width > 0 && height > 0 && width % 2 == 0 && height % 2 == 0,
+                "The size of the universe must be even, got {}x{}", width, height
+            );
+        }
+
+        let cells = self.particles().map(|(_, pos, color)| (pos, color)).collect::<Vec<_>>();
+        self.regions.clear();
+        self.hashmap.clear();
+        self.cells.truncate(1);
+        self.colors.truncate(1);
+        self.alive.truncate(1);
+        self.topology = topology;
+
+        // Bounded universes have all of their regions allocated once and for all
+        if let Some((width, height)) = topology.size() {
+            for y in (0..height).step_by(REGION_SIZE) {
+                for x in (0..width).step_by(REGION_SIZE) {
+                    self.insert_empty_region(x, y);
+                }
+            }
+        }
+
+        self.insert_all(cells);
+    }
+
     /// Gets the cell at `x`, `y`
     pub fn get(&self, x: i64, y: i64) -> usize {
-        let (nearest_x, nearest_y) = nearest_region(x, y);
-
-        if let Some(&region) = self.hashmap.get(&(nearest_x, nearest_y)) {
-            self.regions[region].cells[(y - nearest_y) as usize][(x - nearest_x) as usize]
-        } else {
-            0
+        let (x, y) = match self.topology.normalize(x, y) {
+            Some(pos) => pos,
+            None => return 0,
+        };
+        match self.locate(x, y) {
+            Some((region, x, y)) => self.regions[region].cells[y][x],
+            None => 0,
         }
     }
 
     /// Inserts a cell at `x`, `y`.
     /// Does nothing if a cell already exists there
     pub fn insert(&mut self, x: i64, y: i64, color: usize) {
+        if self.insert_cell(x, y, color) {
+            self.update_regions();
+        }
+    }
+
+    /// Inserts a cell of the given color at each of the positions, skipping the ones that are already occupied.
+    /// The regions are only updated once, which makes it much faster than calling `insert` for each cell
+    pub fn insert_all(&mut self, particles: impl IntoIterator<Item = ((i64, i64), usize)>) {
+        let mut inserted = false;
+        for ((x, y), color) in particles {
+            inserted |= self.insert_cell(x, y, color);
+        }
+        if inserted {
+            self.update_regions();
+        }
+    }
+
+    /// Inserts a cell at `x`, `y` into the grid, without updating the regions; returns false if it lies outside of the universe
+    /// or if a cell already exists there
+    fn insert_cell(&mut self, x: i64, y: i64, color: usize) -> bool {
+        let (x, y) = match self.topology.normalize(x, y) {
+            Some(pos) => pos,
+            None => return false,
+        };
         let nearest = nearest_region(x, y);
         let region = if let Some(region) = self.hashmap.get(&nearest) {
             *region
@@ -83,16 +282,183 @@ impl RegionTree {
             self.insert_empty_region(nearest.0, nearest.1)
         };
 
-        self.regions[region].n_cells += 1;
         if self.regions[region].cells[(y - nearest.1) as usize][(x - nearest.0) as usize] > 0 {
-            return
+            return false
         }
+        self.regions[region].n_cells += 1;
         self.regions[region].cells[(y - nearest.1) as usize][(x - nearest.0) as usize] =
             self.cells.len();
         self.cells.push((x, y));
         self.colors.push(color);
+        self.alive.push(true);
+        true
+    }
+
+    /// Returns the index of the region holding `x`, `y` and the coordinates of that cell within it,
+    /// if the region exists; `x` and `y` must already be normalized
+    fn locate(&self, x: i64, y: i64) -> Option<(usize, usize, usize)> {
+        let (nearest_x, nearest_y) = nearest_region(x, y);
+        self.hashmap
+            .get(&(nearest_x, nearest_y))
+            .map(|&region| (region, (x - nearest_x) as usize, (y - nearest_y) as usize))
+    }
+
+    /// Removes the particle at `x`, `y` from the grid, without updating the regions
+    fn remove_cell(&mut self, x: i64, y: i64) -> Option<usize> {
+        let (x, y) = self.topology.normalize(x, y)?;
+        let (region, lx, ly) = self.locate(x, y)?;
+        let id = std::mem::replace(&mut self.regions[region].cells[ly][lx], 0);
+        if id == 0 {
+            return None
+        }
+        self.regions[region].n_cells -= 1;
+        self.alive[id] = false;
+        Some(id)
+    }
+
+    /// Removes the particle at `x`, `y`, returning its id.
+    /// The ids of the other particles are left untouched; call `compact` to reclaim the id.
+    pub fn remove(&mut self, x: i64, y: i64) -> Option<usize> {
+        let res = self.remove_cell(x, y);
+        if res.is_some() {
+            self.update_regions();
+        }
+        res
+    }
+
+    /// Removes all of the particles within the `width`x`height` rectangle whose top-left corner is `x`, `y`;
+    /// returns the number of removed particles
+    pub fn clear_rect(&mut self, x: i64, y: i64, width: i64, height: i64) -> usize {
+        let (wrap_x, wrap_y) = match self.topology {
+            Topology::Torus { width, height } => (width, height),
+            _ => (i64::MAX, i64::MAX),
+        };
+        let inside = self
+            .particles()
+            .map(|(_, pos, _)| pos)
+            .filter(|&(px, py)| (px - x).rem_euclid(wrap_x) < width && (py - y).rem_euclid(wrap_y) < height)
+            .collect::<Vec<_>>();
+
+        for &(px, py) in &inside {
+            self.remove_cell(px, py);
+        }
+        if !inside.is_empty() {
+            self.update_regions();
+        }
+        inside.len()
+    }
+
+    /// Moves the particle at `from` to `to`, keeping its id and color.
+    /// Returns false if there is no particle at `from`, if `to` is already occupied or if `to` lies outside of the universe.
+    pub fn move_cell(&mut self, from: (i64, i64), to: (i64, i64)) -> bool {
+        let to = match self.topology.normalize(to.0, to.1) {
+            Some(to) => to,
+            None => return false,
+        };
+        if self.get(from.0, from.1) == 0 || self.get(to.0, to.1) > 0 {
+            return false
+        }
+
+        let id = self.remove_cell(from.0, from.1).unwrap();
+        let nearest = nearest_region(to.0, to.1);
+        let region = match self.hashmap.get(&nearest) {
+            Some(&region) => region,
+            None => self.insert_empty_region(nearest.0, nearest.1),
+        };
+        self.regions[region].cells[(to.1 - nearest.1) as usize][(to.0 - nearest.0) as usize] = id;
+        self.regions[region].n_cells += 1;
+        self.cells[id] = to;
+        self.alive[id] = true;
 
         self.update_regions();
+        true
+    }
+
+    /// Renumbers the particles so that the ids of removed particles are reclaimed, keeping the remaining ones in the same order.
+    /// Returns, for each old id, the new id of the particle (or 0 if it was removed).
+    pub fn compact(&mut self) -> Vec<usize> {
+        let mut mapping = vec![0; self.cells.len()];
+        let mut next = 1;
+        for (id, new_id) in mapping.iter_mut().enumerate().skip(1) {
+            if self.alive[id] {
+                *new_id = next;
+                self.cells[next] = self.cells[id];
+                self.colors[next] = self.colors[id];
+                self.alive[next] = true;
+                next += 1;
+            }
+        }
+        self.cells.truncate(next);
+        self.colors.truncate(next);
+        self.alive.truncate(next);
+
+        for region in self.regions.iter_mut().filter(|region| region.n_cells > 0) {
+            for row in region.cells.iter_mut() {
+                for cell in row.iter_mut() {
+                    *cell = mapping[*cell];
+                }
+            }
+        }
+
+        mapping
+    }
+
+    /// Returns the rule the universe follows; use `set_rule` to change it
+    pub fn rule(&self) -> &BlockRule {
+        &self.rule
+    }
+
+    /// Changes the rule the universe follows from the current generation on
+    pub fn set_rule(&mut self, rule: BlockRule) {
+        self.inverse = rule.inverse().table;
+        self.rule = rule;
+    }
+
+    /// Returns the shape of the universe; use `set_topology` to change it
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    /// Returns the regions of the universe, some of which may be empty
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Returns the region containing the cell at `x`, `y`, if there is one
+    pub fn region_at(&self, x: i64, y: i64) -> Option<&Region> {
+        self.hashmap.get(&nearest_region(x, y)).map(|&index| &self.regions[index])
+    }
+
+    /// Returns the largest particle id given so far; ids start at 1
+    pub fn max_id(&self) -> usize {
+        self.cells.len() - 1
+    }
+
+    /// Returns the position of particle `id`, or `None` if it doesn't exist or was removed
+    pub fn position(&self, id: usize) -> Option<(i64, i64)> {
+        self.is_alive(id).then(|| self.cells[id])
+    }
+
+    /// Returns the color of particle `id`, or `None` if it doesn't exist or was removed
+    pub fn color(&self, id: usize) -> Option<usize> {
+        self.is_alive(id).then(|| self.colors[id])
+    }
+
+    /// Returns true if particle `id` exists and wasn't removed
+    pub fn is_alive(&self, id: usize) -> bool {
+        id > 0 && self.alive.get(id).copied().unwrap_or(false)
+    }
+
+    /// Returns the number of particles in the universe
+    pub fn population(&self) -> usize {
+        self.regions.iter().map(|region| region.n_cells).sum()
+    }
+
+    /// Iterates over the particles still in the universe, as `(id, (x, y), color)`
+    pub fn particles(&self) -> impl Iterator<Item = (usize, (i64, i64), usize)> + '_ {
+        (1..self.cells.len())
+            .filter(move |&id| self.alive[id])
+            .map(move |id| (id, self.cells[id], self.colors[id]))
     }
 
     /// Inserts an empty region at `x`, `y`. Does not verify that there already is an empty region there
@@ -111,19 +477,22 @@ impl RegionTree {
         r
     }
 
-    /// Updates the region, pruning the empty ones and creating a border of empty regions
+    /// Updates the region, pruning the empty ones and creating a border of empty regions.
+    /// Does nothing in bounded universes, where all of the regions are always present
     pub fn update_regions(&mut self) {
+        if self.topology != Topology::Plane {
+            return
+        }
+
         {
             let mut to_remove = Vec::new();
             for (index, region) in self.regions.iter().enumerate() {
                 if region.n_cells == 0 {
                     let mut has_neighbor = false;
-                    for neighbor in region.neighbors.iter() {
-                        if let Some(n) = neighbor {
-                            if self.regions[*n].n_cells > 0 {
-                                has_neighbor = true;
-                                break
-                            }
+                    for n in region.neighbors.iter().flatten() {
+                        if self.regions[*n].n_cells > 0 {
+                            has_neighbor = true;
+                            break
                         }
                     }
                     if !has_neighbor {
@@ -132,7 +501,7 @@ impl RegionTree {
                 }
             }
 
-            if to_remove.len() > 0 {
+            if !to_remove.is_empty() {
                 let mut n = 0;
 
                 for &index in &to_remove {
@@ -152,14 +521,14 @@ impl RegionTree {
 
         for region in self.regions.iter_mut() {
             region.neighbors = [
-                self.hashmap.get(&(region.x, region.y - R)).map(|x| *x),
-                self.hashmap.get(&(region.x + R, region.y - R)).map(|x| *x),
-                self.hashmap.get(&(region.x + R, region.y)).map(|x| *x),
-                self.hashmap.get(&(region.x + R, region.y + R)).map(|x| *x),
-                self.hashmap.get(&(region.x, region.y + R)).map(|x| *x),
-                self.hashmap.get(&(region.x - R, region.y + R)).map(|x| *x),
-                self.hashmap.get(&(region.x - R, region.y)).map(|x| *x),
-                self.hashmap.get(&(region.x - R, region.y - R)).map(|x| *x),
+                self.hashmap.get(&(region.x, region.y - R)).copied(),
+                self.hashmap.get(&(region.x + R, region.y - R)).copied(),
+                self.hashmap.get(&(region.x + R, region.y)).copied(),
+                self.hashmap.get(&(region.x + R, region.y + R)).copied(),
+                self.hashmap.get(&(region.x, region.y + R)).copied(),
+                self.hashmap.get(&(region.x - R, region.y + R)).copied(),
+                self.hashmap.get(&(region.x - R, region.y)).copied(),
+                self.hashmap.get(&(region.x - R, region.y - R)).copied(),
             ];
         }
 
@@ -167,7 +536,7 @@ impl RegionTree {
             if self.regions[region].n_cells > 0 {
                 for i in 0..8 {
                     if self.regions[region].neighbors[i].is_none() {
-                        let dx = if i >= 1 && i <= 3 {
+                        let dx = if (1..=3).contains(&i) {
                             R
                         } else if i >= 5 {
                             -R
@@ -176,7 +545,7 @@ impl RegionTree {
                         };
                         let dy = if i == 2 || i == 6 {
                             0
-                        } else if i >= 3 && i <= 5 {
+                        } else if (3..=5).contains(&i) {
                             R
                         } else {
                             -R
@@ -190,134 +559,340 @@ impl RegionTree {
 
     /// Steps the simulation forward by one generation
     pub fn tick(&mut self) {
-        if self.step % (REGION_SIZE - 2) == 0 {
+        if self.step.is_multiple_of(REGION_SIZE - 2) {
             self.update_regions();
         }
 
-        if self.step % 2 == 0 {
+        let phase = self.step % 2;
+        self.update_phase(phase, self.rule.table[phase]);
+
+        self.step += 1;
+    }
+
+    /// Steps the simulation backward by one generation, undoing the last `tick`.
+    /// Does nothing if the simulation is at step 0.
+    pub fn untick(&mut self) {
+        if self.step == 0 {
+            return
+        }
+
+        if self.step.is_multiple_of(REGION_SIZE - 2) {
+            self.update_regions();
+        }
+
+        self.step -= 1;
+
+        let phase = self.step % 2;
+        self.update_phase(phase, self.inverse[phase]);
+    }
+
+    /// Updates every 2x2 square of the Margolus partition for `phase`, following `table`
+    fn update_phase(&mut self, phase: usize, table: [Permutation; 16]) {
+        if self.threads > 1 {
+            return self.update_phase_parallel(phase, table)
+        }
+
+        let cells = &mut self.cells;
+        let topology = self.topology;
+        let mut set_cell = |id: usize, pos: (i64, i64)| cells[id] = pos;
+        if phase == 0 {
             // Easy
-            for mut region in self.regions.iter_mut() {
+            for region in self.regions.iter_mut() {
                 if region.n_cells == 0 {
                     continue
                 }
-                update_simple(&mut region, &mut self.cells, 0, REGION_SIZE / 2);
+                let limits = square_limits(&topology, region);
+                update_simple(region, &mut set_cell, 0, REGION_SIZE / 2, limits, &table);
             }
         } else {
             // Do the easy ones first
-            for mut region in self.regions.iter_mut() {
+            for region in self.regions.iter_mut() {
                 if region.n_cells == 0 {
                     continue
                 }
-                update_simple(&mut region, &mut self.cells, 1, REGION_SIZE / 2 - 1);
+                let limits = square_limits(&topology, region);
+                update_simple(region, &mut set_cell, 1, REGION_SIZE / 2 - 1, limits, &table);
             }
             // Now do the hard ones
             for i in 0..self.regions.len() {
-                {
-                    // Edges
-                    let a = REGION_SIZE - 1;
-                    let right = self.regions[i].neighbors[2];
-                    let down = self.regions[i].neighbors[4];
-                    for sb in 0..(REGION_SIZE / 2 - 1) {
-                        let b = sb + sb + 1;
-                        if let Some(right) = right {
-                            self.update_single(a, b, i, right, right, i);
-                        }
-                        if let Some(down) = down {
-                            self.update_single(b, a, i, i, down, down);
-                        }
+                for (x, y, indices) in self.boundary_squares(i) {
+                    if let Some(square) = self.next_square(x, y, indices, &table) {
+                        self.write_square(x, y, indices, square);
                     }
                 }
-                {
-                    // Corner
-                    let x = REGION_SIZE - 1;
-                    let y = REGION_SIZE - 1;
-                    let right = self.regions[i].neighbors[2];
-                    let downright = self.regions[i].neighbors[3];
-                    let down = self.regions[i].neighbors[4];
-                    if let (Some(right), Some(downright), Some(down)) = (right, downright, down) {
-                        self.update_single(x, y, i, right, downright, down);
+            }
+            self.update_seams(&table);
+            self.update_walls();
+        }
+    }
+
+    /// Same as `update_phase`, but splits the work across `self.threads` threads.
+    /// The squares of a phase don't overlap, so the result is identical to that of `update_phase`.
+    fn update_phase_parallel(&mut self, phase: usize, table: [Permutation; 16]) {
+        let threads = self.threads;
+        let topology = self.topology;
+        let chunk_size = self.regions.len().div_ceil(threads).max(1);
+        let (offset, len) = if phase == 0 {
+            (0, REGION_SIZE / 2)
+        } else {
+            (1, REGION_SIZE / 2 - 1)
+        };
+
+        // Interior pass: every thread owns a set of regions and reports which particles moved
+        let moves = std::thread::scope(|scope| {
+            let handles = self.regions.chunks_mut(chunk_size).map(|chunk| {
+                scope.spawn(move || {
+                    let mut moves = Vec::new();
+                    let mut set_cell = |id: usize, pos: (i64, i64)| moves.push((id, pos));
+                    for region in chunk.iter_mut() {
+                        if region.n_cells > 0 {
+                            let limits = square_limits(&topology, region);
+                            update_simple(region, &mut set_cell, offset, len, limits, &table);
+                        }
                     }
+                    moves
+                })
+            }).collect::<Vec<_>>();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+        });
+        for (id, pos) in moves.into_iter().flatten() {
+            self.cells[id] = pos;
+        }
+
+        if phase == 0 {
+            return
+        }
+
+        // Boundary pass: the new contents of the squares are computed in parallel, then written back
+        let updates = {
+            let tree = &*self;
+            std::thread::scope(|scope| {
+                let handles = (0..tree.regions.len()).step_by(chunk_size).map(|start| {
+                    scope.spawn(move || {
+                        let mut updates = Vec::new();
+                        for i in start..(start + chunk_size).min(tree.regions.len()) {
+                            for (x, y, indices) in tree.boundary_squares(i) {
+                                if let Some(square) = tree.next_square(x, y, indices, &table) {
+                                    updates.push((x, y, indices, square));
+                                }
+                            }
+                        }
+                        updates
+                    })
+                }).collect::<Vec<_>>();
+                handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+            })
+        };
+        for (x, y, indices, square) in updates.into_iter().flatten() {
+            self.write_square(x, y, indices, square);
+        }
+        self.update_seams(&table);
+        self.update_walls();
+    }
+
+    /// Reflects the particles off the walls of a box on the odd phase; does nothing in other topologies.
+    /// Only half of each square crossing a wall lies inside the box: its two cells are swapped, so that a particle
+    /// reaching the wall moves along it instead of through it. Swapping is its own inverse, so `untick` uses it as well.
+    /// The squares at the corners of the box only hold one cell, which is left untouched.
+    fn update_walls(&mut self) {
+        let (width, height) = match self.topology {
+            Topology::Box { width, height } => (width, height),
+            _ => return,
+        };
+
+        let vertical = (1..height - 1).step_by(2).flat_map(|y| [((0, y), (0, y + 1)), ((width - 1, y), (width - 1, y + 1))]);
+        let horizontal = (1..width - 1).step_by(2).flat_map(|x| [((x, 0), (x + 1, 0)), ((x, height - 1), (x + 1, height - 1))]);
+        for (first, second) in vertical.chain(horizontal) {
+            // Index of the region and local coordinates of each cell
+            let [a, b] = [first, second].map(|(x, y)| {
+                let (rx, ry) = nearest_region(x, y);
+                (self.hashmap[&(rx, ry)], (x - rx) as usize, (y - ry) as usize)
+            });
+            let a_id = self.regions[a.0].cells[a.2][a.1];
+            let b_id = self.regions[b.0].cells[b.2][b.1];
+            if a_id == 0 && b_id == 0 {
+                continue
+            }
+
+            self.regions[a.0].cells[a.2][a.1] = b_id;
+            self.regions[b.0].cells[b.2][b.1] = a_id;
+            if (a_id > 0) != (b_id > 0) && a.0 != b.0 {
+                let (from, to) = if a_id > 0 { (a.0, b.0) } else { (b.0, a.0) };
+                self.regions[from].n_cells -= 1;
+                self.regions[to].n_cells += 1;
+            }
+            if a_id > 0 {
+                self.cells[a_id] = second;
+            }
+            if b_id > 0 {
+                self.cells[b_id] = first;
+            }
+        }
+    }
+
+    /// Updates the squares of the odd phase which wrap around the edges of a torus; does nothing in other topologies
+    fn update_seams(&mut self, table: &[Permutation; 16]) {
+        let (width, height) = match self.topology {
+            Topology::Torus { width, height } => (width, height),
+            _ => return,
+        };
+
+        let vertical = (1..height - 1).step_by(2).map(|y| (width - 1, y));
+        let horizontal = (1..width - 1).step_by(2).map(|x| (x, height - 1));
+        let corner = std::iter::once((width - 1, height - 1));
+        for (x, y) in vertical.chain(horizontal).chain(corner) {
+            let corners = [
+                (x, y),
+                ((x + 1) % width, y),
+                ((x + 1) % width, (y + 1) % height),
+                (x, (y + 1) % height),
+            ];
+            // Index of the region and local coordinates of each corner
+            let locations = corners.map(|(x, y)| {
+                let (rx, ry) = nearest_region(x, y);
+                (self.hashmap[&(rx, ry)], (x - rx) as usize, (y - ry) as usize)
+            });
+            let square = locations.map(|(region, x, y)| self.regions[region].cells[y][x]);
+            let mask = square.iter().enumerate().fold(0, |mask, (i, &id)| mask | ((id > 0) as usize) << i);
+            if table[mask] == IDENTITY {
+                continue
+            }
+
+            let new_square = permute(square, table[mask]);
+            for (((region, lx, ly), pos), (&old, &new)) in locations.iter().zip(corners.iter()).zip(square.iter().zip(new_square.iter())) {
+                let region = &mut self.regions[*region];
+                region.cells[*ly][*lx] = new;
+                region.n_cells = region.n_cells + (new > 0) as usize - (old > 0) as usize;
+                if new > 0 {
+                    self.cells[new] = *pos;
                 }
             }
         }
+    }
 
-        self.step += 1;
+    /// Lists the 2x2 squares of the odd phase whose top-left corner lies in region `i` but which span over several regions,
+    /// as `(x, y, [a, b, c, d])`, with `a`, `b`, `c` and `d` the indices of the regions holding each corner of the square
+    fn boundary_squares(&self, i: usize) -> impl Iterator<Item = (usize, usize, [usize; 4])> {
+        let a = REGION_SIZE - 1;
+        let right = self.regions[i].neighbors[2];
+        let downright = self.regions[i].neighbors[3];
+        let down = self.regions[i].neighbors[4];
+
+        // Edges
+        let edges = (0..(REGION_SIZE / 2 - 1)).flat_map(move |sb| {
+            let b = sb + sb + 1;
+            let right = right.map(|right| (a, b, [i, right, right, i]));
+            let down = down.map(|down| (b, a, [i, i, down, down]));
+            right.into_iter().chain(down)
+        });
+
+        // Corner
+        let corner = match (right, downright, down) {
+            (Some(right), Some(downright), Some(down)) => Some((a, a, [i, right, downright, down])),
+            _ => None,
+        };
+
+        let (rx, ry) = (self.regions[i].x, self.regions[i].y);
+        let topology = self.topology;
+        edges.chain(corner).filter(move |&(x, y, _)| topology.contains_square(rx + x as i64, ry + y as i64))
     }
 
-    /// Update a single 2x2 square, given the set of neighboring regions
-    fn update_single(
-        &mut self,
-        x: usize,
-        y: usize,
-        a_i: usize,
-        b_i: usize,
-        c_i: usize,
-        d_i: usize,
-    ) {
+    /// Computes the new contents of a single 2x2 square, given the set of neighboring regions.
+    /// Returns `None` if the square is left untouched.
+    fn next_square(&self, x: usize, y: usize, indices: [usize; 4], table: &[Permutation; 16]) -> Option<[usize; 4]> {
+        let [a_i, b_i, c_i, d_i] = indices;
         let a = self.regions[a_i].cells[y][x];
         let b = self.regions[b_i].cells[y][(x + 1) % REGION_SIZE];
         let c = self.regions[c_i].cells[(y + 1) % REGION_SIZE][(x + 1) % REGION_SIZE];
         let d = self.regions[d_i].cells[(y + 1) % REGION_SIZE][x];
-        let n = (a > 0) as u8 + (b > 0) as u8 + (c > 0) as u8 + (d > 0) as u8;
-        if n == 1 {
+        let mask = (a > 0) as usize | ((b > 0) as usize) << 1 | ((c > 0) as usize) << 2 | ((d > 0) as usize) << 3;
+        if table[mask] != IDENTITY {
             // println!("~ {} {}: {} {} {} {}", x, y, a, b, c, d);
-            self.regions[a_i].cells[y][x] = d;
-            self.regions[b_i].cells[y][(x + 1) % REGION_SIZE] = a;
-            self.regions[c_i].cells[(y + 1) % REGION_SIZE][(x + 1) % REGION_SIZE] = b;
-            self.regions[d_i].cells[(y + 1) % REGION_SIZE][x] = c;
-
-            self.regions[a_i].n_cells = self.regions[a_i].n_cells + (d > 0) as usize - (a > 0) as usize;
-            self.regions[b_i].n_cells = self.regions[b_i].n_cells + (a > 0) as usize - (b > 0) as usize;
-            self.regions[c_i].n_cells = self.regions[c_i].n_cells + (b > 0) as usize - (c > 0) as usize;
-            self.regions[d_i].n_cells = self.regions[d_i].n_cells + (c > 0) as usize - (d > 0) as usize;
-
-            self.cells[a] = (
-                self.regions[a_i].x + x as i64 + 1,
-                self.regions[a_i].y + y as i64,
-            );
-            self.cells[b] = (
-                self.regions[a_i].x + x as i64 + 1,
-                self.regions[a_i].y + y as i64 + 1,
-            );
-            self.cells[c] = (
-                self.regions[a_i].x + x as i64,
-                self.regions[a_i].y + y as i64 + 1,
-            );
-            self.cells[d] = (
-                self.regions[a_i].x + x as i64,
-                self.regions[a_i].y + y as i64,
-            );
+            Some(permute([a, b, c, d], table[mask]))
+        } else {
+            None
+        }
+    }
+
+    /// Writes the contents of a single 2x2 square, given the set of neighboring regions
+    fn write_square(&mut self, x: usize, y: usize, indices: [usize; 4], square: [usize; 4]) {
+        let [a_i, b_i, c_i, d_i] = indices;
+        let [na, nb, nc, nd] = square;
+        let a = std::mem::replace(&mut self.regions[a_i].cells[y][x], na);
+        let b = std::mem::replace(&mut self.regions[b_i].cells[y][(x + 1) % REGION_SIZE], nb);
+        let c = std::mem::replace(&mut self.regions[c_i].cells[(y + 1) % REGION_SIZE][(x + 1) % REGION_SIZE], nc);
+        let d = std::mem::replace(&mut self.regions[d_i].cells[(y + 1) % REGION_SIZE][x], nd);
+
+        self.regions[a_i].n_cells = self.regions[a_i].n_cells + (na > 0) as usize - (a > 0) as usize;
+        self.regions[b_i].n_cells = self.regions[b_i].n_cells + (nb > 0) as usize - (b > 0) as usize;
+        self.regions[c_i].n_cells = self.regions[c_i].n_cells + (nc > 0) as usize - (c > 0) as usize;
+        self.regions[d_i].n_cells = self.regions[d_i].n_cells + (nd > 0) as usize - (d > 0) as usize;
+
+        let (x, y) = (self.regions[a_i].x + x as i64, self.regions[a_i].y + y as i64);
+        for (id, pos) in [(na, (x, y)), (nb, (x + 1, y)), (nc, (x + 1, y + 1)), (nd, (x, y + 1))] {
+            if id > 0 {
+                self.cells[id] = pos;
+            }
         }
     }
 }
 
-/// Update all of the 2x2 square fully enclosed within a region
+/// Update all of the 2x2 square fully enclosed within a region;
+/// `set_cell` is called with the new position of every particle that moved
 #[inline]
-fn update_simple(region: &mut Region, cells: &mut Vec<(i64, i64)>, offset: usize, len: usize) {
+fn update_simple<F: FnMut(usize, (i64, i64))>(
+    region: &mut Region,
+    set_cell: &mut F,
+    offset: usize,
+    len: usize,
+    limits: (usize, usize),
+    table: &[Permutation; 16],
+) {
     for sy in 0..len {
         let y = sy + sy + offset;
+        if y + 1 >= limits.1 {
+            break
+        }
         for sx in 0..len {
             let x = sx + sx + offset;
+            if x + 1 >= limits.0 {
+                break
+            }
             let a = region.cells[y][x];
             let b = region.cells[y][x + 1];
             let c = region.cells[y + 1][x + 1];
             let d = region.cells[y + 1][x];
-            let n = (a > 0) as u8 + (b > 0) as u8 + (c > 0) as u8 + (d > 0) as u8;
-            if n == 1 {
+            let mask = (a > 0) as usize | ((b > 0) as usize) << 1 | ((c > 0) as usize) << 2 | ((d > 0) as usize) << 3;
+            if table[mask] != IDENTITY {
                 // println!("{} {}: {} {} {} {}", x, y, a, b, c, d);
-                region.cells[y][x] = d;
-                region.cells[y][x + 1] = a;
-                region.cells[y + 1][x + 1] = b;
-                region.cells[y + 1][x] = c;
-                cells[a] = (region.x + x as i64 + 1, region.y + y as i64);
-                cells[b] = (region.x + x as i64 + 1, region.y + y as i64 + 1);
-                cells[c] = (region.x + x as i64, region.y + y as i64 + 1);
-                cells[d] = (region.x + x as i64, region.y + y as i64);
+                let [na, nb, nc, nd] = permute([a, b, c, d], table[mask]);
+                region.cells[y][x] = na;
+                region.cells[y][x + 1] = nb;
+                region.cells[y + 1][x + 1] = nc;
+                region.cells[y + 1][x] = nd;
+                let (x, y) = (region.x + x as i64, region.y + y as i64);
+                for (id, pos) in [(na, (x, y)), (nb, (x + 1, y)), (nc, (x + 1, y + 1)), (nd, (x, y + 1))] {
+                    if id > 0 {
+                        set_cell(id, pos);
+                    }
+                }
             }
         }
     }
 }
 
+/// Returns the local coordinates past which the squares of `region` cross the edges of the universe:
+/// a square is only updated if both `x + 1` and `y + 1` are below these limits
+fn square_limits(topology: &Topology, region: &Region) -> (usize, usize) {
+    match topology.size() {
+        Some((width, height)) => (
+            (width - region.x).clamp(0, R) as usize,
+            (height - region.y).clamp(0, R) as usize,
+        ),
+        None => (REGION_SIZE, REGION_SIZE),
+    }
+}
+
 /// Gets the region coordinate of the nearest region (that where the `(x, y)` belongs)
 pub fn nearest_region(x: i64, y: i64) -> (i64, i64) {
     (
@@ -325,3 +900,205 @@ pub fn nearest_region(x: i64, y: i64) -> (i64, i64) {
         y.div_euclid(R) * R
     )
 }
+
+#[test]
+fn test_untick() {
+    for rule in &["single-rotation", "double-rotation", "billiard-ball", "critters"] {
+        let mut tree = RegionTree::with_rule(rule.parse().unwrap());
+        // Two spaceships heading towards each other
+        for &(x, y) in &[(0, 0), (1, 0), (0, 2), (1, 2), (-40, 0), (-39, 0), (-40, 2), (-39, 2), (5, 17)] {
+            tree.insert(x, y, 0);
+        }
+        let cells = tree.cells.clone();
+        let grid = (-64..64).flat_map(|y| (-64..64).map(move |x| (x, y))).map(|(x, y)| tree.get(x, y)).collect::<Vec<_>>();
+
+        for _ in 0..137 {
+            tree.tick();
+        }
+        assert_ne!(tree.cells, cells);
+        for _ in 0..137 {
+            tree.untick();
+        }
+
+        assert_eq!(tree.step, 0);
+        assert_eq!(tree.cells, cells);
+        assert_eq!(
+            (-64..64).flat_map(|y| (-64..64).map(move |x| (x, y))).map(|(x, y)| tree.get(x, y)).collect::<Vec<_>>(),
+            grid
+        );
+    }
+}
+
+#[test]
+fn test_tick_parallel() {
+    let mut serial = RegionTree::new();
+    let mut seed = 12345u64;
+    for _ in 0..400 {
+        // Small xorshift generator, to spread cells over many regions
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        serial.insert((seed % 128) as i64 - 64, ((seed >> 32) % 128) as i64 - 64, 0);
+    }
+    let mut parallel = serial.clone();
+    parallel.threads = 4;
+
+    for _ in 0..100 {
+        serial.tick();
+        parallel.tick();
+    }
+    assert_eq!(serial.cells, parallel.cells);
+    for (x, y) in (-96..96).flat_map(|y| (-96..96).map(move |x| (x, y))) {
+        assert_eq!(serial.get(x, y), parallel.get(x, y));
+    }
+}
+
+#[test]
+fn test_insert_twice() {
+    let mut tree = RegionTree::new();
+    tree.insert(3, 5, 1);
+    tree.insert(3, 5, 2);
+    assert_eq!(tree.cells.len(), 2);
+    assert_eq!(tree.colors[1], 1);
+    // Otherwise the region would never be seen as empty, and never be pruned
+    assert_eq!(tree.regions.iter().map(|region| region.n_cells).sum::<usize>(), 1);
+}
+
+#[test]
+fn test_topology() {
+    for &topology in &[Topology::Torus { width: 20, height: 34 }, Topology::Box { width: 20, height: 34 }] {
+        let (width, height) = topology.size().unwrap();
+        let mut tree = RegionTree::new();
+        tree.set_topology(topology);
+        let mut grid = vec![false; (width * height) as usize];
+        let mut seed = 55555u64;
+        for _ in 0..120 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let (x, y) = ((seed % 64) as i64 - 32, ((seed >> 32) % 64) as i64 - 32);
+            tree.insert(x, y, 0);
+            if let Some((x, y)) = topology.normalize(x, y) {
+                grid[(x + y * width) as usize] = true;
+            }
+        }
+        let population = grid.iter().filter(|&&cell| cell).count();
+        assert_eq!(tree.cells.len() - 1, population);
+
+        // Naive implementation of the rule on a bounded grid
+        for step in 0..60 {
+            let phase = step % 2;
+            let table = tree.rule.table[phase];
+            // The squares crossing the walls of a box start outside of it
+            let start = match topology {
+                Topology::Box { .. } => -(phase as i64),
+                _ => phase as i64,
+            };
+            for y in (start..height).step_by(2) {
+                for x in (start..width).step_by(2) {
+                    let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+                    if let Topology::Box { .. } = topology {
+                        let inside = corners
+                            .iter()
+                            .filter(|&&(x, y)| x >= 0 && y >= 0 && x < width && y < height)
+                            .map(|&(x, y)| (x + y * width) as usize)
+                            .collect::<Vec<_>>();
+                        if let [a, b] = inside[..] {
+                            grid.swap(a, b);
+                        }
+                        if inside.len() < 4 {
+                            continue
+                        }
+                    }
+                    let corners = corners.map(|(x, y)| (x.rem_euclid(width) + y.rem_euclid(height) * width) as usize);
+                    let square = corners.map(|i| grid[i]);
+                    let mask = square.iter().enumerate().fold(0, |mask, (i, &cell)| mask | (cell as usize) << i);
+                    for (&i, cell) in corners.iter().zip(permute(square, table[mask]).iter()) {
+                        grid[i] = *cell;
+                    }
+                }
+            }
+            tree.tick();
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                assert_eq!(tree.get(x, y) > 0, grid[(x + y * width) as usize]);
+                if let Topology::Torus { .. } = topology {
+                    assert_eq!(tree.get(x, y), tree.get(x + width, y - height));
+                } else {
+                    assert_eq!(tree.get(x + width, y - height), 0);
+                }
+            }
+        }
+        assert_eq!(tree.regions.iter().map(|region| region.n_cells).sum::<usize>(), population);
+
+        for _ in 0..60 {
+            tree.untick();
+        }
+        assert_eq!(tree.step, 0);
+    }
+}
+
+#[test]
+fn test_box_walls() {
+    // The lightest spaceship, moving left, bounces off the left wall and comes back moving right
+    let mut tree = RegionTree::new();
+    tree.set_topology(Topology::Box { width: 40, height: 20 });
+    for &(x, y) in &[(20, 8), (21, 8), (20, 10), (21, 10)] {
+        tree.insert(x, y, 0);
+    }
+    let sorted_cells = |tree: &RegionTree| {
+        let mut cells = tree.particles().map(|(_, position, _)| position).collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    };
+    for _ in 0..180 {
+        tree.tick();
+    }
+    let before = sorted_cells(&tree);
+    assert!(before.iter().all(|&(x, _)| x < 12));
+    for _ in 0..12 {
+        tree.tick();
+    }
+    assert_eq!(sorted_cells(&tree), before.iter().map(|&(x, y)| (x + 2, y)).collect::<Vec<_>>());
+
+    // The walls are reversible as well
+    for _ in 0..192 {
+        tree.untick();
+    }
+    assert_eq!(sorted_cells(&tree), vec![(20, 8), (20, 10), (21, 8), (21, 10)]);
+}
+
+#[test]
+fn test_edit() {
+    let mut tree = RegionTree::new();
+    for x in 0..40 {
+        tree.insert(x, x % 3, x as usize % 5);
+    }
+    assert_eq!(tree.population(), 40);
+
+    assert_eq!(tree.remove(3, 0), Some(4));
+    assert_eq!(tree.remove(3, 0), None);
+    assert_eq!(tree.clear_rect(10, 0, 20, 2), 13);
+    assert_eq!(tree.population(), 26);
+    assert!(tree.move_cell((0, 0), (-20, -20)));
+    assert!(!tree.move_cell((0, 0), (1, 1)));
+    assert!(!tree.move_cell((1, 1), (2, 2)));
+    assert_eq!(tree.get(-20, -20), 1);
+
+    let colors = tree.particles().map(|(_, pos, color)| (pos, color)).collect::<Vec<_>>();
+    let mapping = tree.compact();
+    assert_eq!(mapping[5], 4);
+    assert_eq!(tree.cells.len(), 27);
+    assert_eq!(tree.particles().map(|(_, pos, color)| (pos, color)).collect::<Vec<_>>(), colors);
+    for (id, (x, y), _) in tree.particles() {
+        assert_eq!(tree.get(x, y), id);
+    }
+    assert_eq!(tree.regions.iter().map(|region| region.n_cells).sum::<usize>(), 26);
+
+    // The regions that became empty are pruned
+    tree.clear_rect(-100, -100, 200, 200);
+    tree.update_regions();
+    assert!(tree.regions.is_empty());
+}
@@ -0,0 +1,521 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use super::display::COLORS;
+use super::regions::{RegionTree, Topology};
+use super::rules::{BlockRule, RuleError};
+
+/// How the `x` and `y` header parameters are understood
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RleMode {
+    /// `x` and `y` are the coordinates of the top-left corner of the pattern, and may be given again further down
+    /// to place several patterns
+    #[default]
+    Legacy,
+    /// `x` and `y` are the width and height of the pattern, as in standard RLE; the top-left corner is `(0, 0)`,
+    /// unless it is given by a `#CXRLE Pos=X,Y` line
+    Standard,
+}
+
+/// Display parameters read from the header of an RLE file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RleSettings {
+    /// Number of simulation steps between frames (`s`)
+    pub steps: usize,
+    /// Minimum time interval between frames, in milliseconds (`i`)
+    pub interval: u32,
+    /// Smoothing factor of the interpolation (`u`)
+    pub smoothing: usize,
+}
+
+impl Default for RleSettings {
+    fn default() -> Self {
+        Self {
+            steps: 1,
+            interval: 100,
+            smoothing: 4,
+        }
+    }
+}
+
+/// What went wrong while parsing an RLE file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RleErrorKind {
+    /// A number couldn't be read, or is out of range
+    InvalidNumber(String),
+    /// A character that has no meaning in the body of the pattern
+    UnexpectedChar(char),
+    /// A header parameter that isn't known
+    UnknownKey(String),
+    /// A header entry without a `=` sign
+    MissingValue(String),
+    /// The rule couldn't be parsed
+    Rule(RuleError),
+    /// The topology couldn't be parsed
+    Topology(String),
+    /// In standard mode, the header must come before the body
+    HeaderAfterBody,
+    /// A color tag beyond the colors of the palette
+    InvalidColor(i64),
+    /// The input couldn't be read
+    Io(String),
+}
+
+/// An error found while parsing an RLE file; lines and columns start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RleError {
+    /// Line at which the error was found
+    pub line: usize,
+    /// Column at which the error was found, if it was found in the line itself rather than while reading it
+    pub column: Option<usize>,
+    /// What went wrong
+    pub kind: RleErrorKind,
+}
+
+impl fmt::Display for RleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RleErrorKind::InvalidNumber(number) => write!(f, "invalid number {:?}", number),
+            RleErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            RleErrorKind::UnknownKey(key) => write!(f, "unknown header parameter {:?}", key),
+            RleErrorKind::MissingValue(key) => write!(f, "expected a value for {:?}", key),
+            RleErrorKind::Rule(err) => write!(f, "{}", err),
+            RleErrorKind::Topology(err) => write!(f, "{}", err),
+            RleErrorKind::HeaderAfterBody => write!(f, "the header must come before the pattern"),
+            RleErrorKind::InvalidColor(color) => write!(f, "color {} is out of the palette, which has {} colors", color, COLORS.len()),
+            RleErrorKind::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl fmt::Display for RleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "line {}, column {}: {}", self.line, column, self.kind),
+            None => write!(f, "line {}: {}", self.line, self.kind),
+        }
+    }
+}
+
+impl std::error::Error for RleError {}
+
+/// Parses an RLE file one line at a time, so that it can be read from an interactive input until its closing `!`
+#[derive(Debug, Clone)]
+pub struct RleParser {
+    mode: RleMode,
+    tree: RegionTree,
+    settings: RleSettings,
+    /// Column that `$` goes back to
+    origin_x: i64,
+    x: i64,
+    y: i64,
+    color: usize,
+    /// Digits of the run count being read, and the column of its first digit
+    count: String,
+    count_column: usize,
+    line: usize,
+    body_started: bool,
+    finished: bool,
+}
+
+impl RleParser {
+    /// Creates a parser for a file in `mode`
+    pub fn new(mode: RleMode) -> Self {
+        Self {
+            mode,
+            tree: RegionTree::new(),
+            settings: RleSettings::default(),
+            origin_x: 0,
+            x: 0,
+            y: 0,
+            color: 0,
+            count: String::new(),
+            count_column: 0,
+            line: 0,
+            body_started: false,
+            finished: false,
+        }
+    }
+
+    /// Returns true once the closing `!` has been read; any further input is ignored
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the pattern and display parameters read so far
+    pub fn finish(self) -> (RegionTree, RleSettings) {
+        (self.tree, self.settings)
+    }
+
+    fn error(&self, column: usize, kind: RleErrorKind) -> RleError {
+        RleError {
+            line: self.line,
+            column: Some(column),
+            kind,
+        }
+    }
+
+    /// Parses the next line of the input
+    pub fn parse_line(&mut self, line: &str) -> Result<(), RleError> {
+        self.line += 1;
+        if self.finished {
+            return Ok(())
+        }
+
+        let trimmed = line.trim_start();
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            if self.mode == RleMode::Standard {
+                self.parse_comment(comment, line.len() - trimmed.len() + 2)?;
+            }
+            Ok(())
+        } else if line.contains('=') {
+            if self.mode == RleMode::Standard && self.body_started {
+                return Err(self.error(1, RleErrorKind::HeaderAfterBody))
+            }
+            self.parse_header(line)
+        } else {
+            self.parse_body(line)
+        }
+    }
+
+    /// Reads the position and generation of the pattern from a `#CXRLE Pos=X,Y Gen=G` comment
+    fn parse_comment(&mut self, comment: &str, column: usize) -> Result<(), RleError> {
+        let body = match comment.strip_prefix("CXRLE") {
+            Some(body) => body,
+            None => return Ok(()),
+        };
+
+        let mut offset = column + "CXRLE".len();
+        for word in body.split(' ') {
+            if let Some(position) = word.strip_prefix("Pos=") {
+                let mut coordinates = position.splitn(2, ',').map(|n| n.trim().parse::<i64>());
+                match (coordinates.next(), coordinates.next()) {
+                    (Some(Ok(x)), Some(Ok(y))) => {
+                        self.origin_x = x;
+                        self.x = x;
+                        self.y = y;
+                    }
+                    _ => {
+                        return Err(self.error(offset + 4, RleErrorKind::InvalidNumber(position.to_string())))
+                    }
+                }
+            } else if let Some(generation) = word.strip_prefix("Gen=") {
+                self.tree.step = generation
+                    .parse()
+                    .map_err(|_| self.error(offset + 4, RleErrorKind::InvalidNumber(generation.to_string())))?;
+            }
+            offset += word.chars().count() + 1;
+        }
+        Ok(())
+    }
+
+    /// Parses a line of `key = value` entries, separated by commas
+    fn parse_header(&mut self, line: &str) -> Result<(), RleError> {
+        // Entries are split on commas, except for those found in values, such as the ones of rules in the M notation
+        let mut entries: Vec<(usize, String)> = Vec::new();
+        let mut column = 1;
+        for segment in line.split(',') {
+            match entries.last_mut() {
+                Some((_, entry)) if !segment.contains('=') => {
+                    entry.push(',');
+                    entry.push_str(segment);
+                }
+                _ => entries.push((column, segment.to_string())),
+            }
+            column += segment.chars().count() + 1;
+        }
+
+        for (column, entry) in entries {
+            if entry.trim().is_empty() {
+                continue
+            }
+            let mut pair = entry.splitn(2, '=');
+            let key = pair.next().unwrap_or("").trim();
+            let value = match pair.next() {
+                Some(value) => value.trim(),
+                None => return Err(self.error(column, RleErrorKind::MissingValue(key.to_string()))),
+            };
+            let (before, after) = entry.split_at(entry.find('=').unwrap_or(0) + 1);
+            let value_column = column + before.chars().count() + after.chars().take_while(|c| c.is_whitespace()).count();
+            self.parse_entry(key, value, column, value_column)?;
+        }
+        Ok(())
+    }
+
+    fn parse_entry(&mut self, key: &str, value: &str, column: usize, value_column: usize) -> Result<(), RleError> {
+        let invalid = |parser: &Self| parser.error(value_column, RleErrorKind::InvalidNumber(value.to_string()));
+        match key {
+            "x" => {
+                let x = value.parse::<i64>().map_err(|_| invalid(self))?;
+                if self.mode == RleMode::Legacy {
+                    self.origin_x = x;
+                    self.x = x;
+                }
+            }
+            "y" => {
+                let y = value.parse::<i64>().map_err(|_| invalid(self))?;
+                if self.mode == RleMode::Legacy {
+                    self.y = y;
+                }
+            }
+            "g" | "gen" => self.tree.step = value.parse().map_err(|_| invalid(self))?,
+            "s" => self.settings.steps = value.parse().map_err(|_| invalid(self))?,
+            "i" => self.settings.interval = value.parse().map_err(|_| invalid(self))?,
+            "u" => self.settings.smoothing = value.parse().map_err(|_| invalid(self))?,
+            "r" | "rule" => {
+                let rule = value
+                    .parse::<BlockRule>()
+                    .map_err(|err| self.error(value_column, RleErrorKind::Rule(err)))?;
+                self.tree.set_rule(rule);
+            }
+            "t" | "topology" => {
+                let topology = value
+                    .parse::<Topology>()
+                    .map_err(|err| self.error(value_column, RleErrorKind::Topology(err)))?;
+                self.tree.set_topology(topology);
+            }
+            _ => return Err(self.error(column, RleErrorKind::UnknownKey(key.to_string()))),
+        }
+        Ok(())
+    }
+
+    /// Takes the run count read so far, which defaults to 1
+    fn take_count(&mut self) -> Result<i64, RleError> {
+        if self.count.is_empty() {
+            return Ok(1)
+        }
+        let count = std::mem::take(&mut self.count);
+        count
+            .parse::<i64>()
+            .map_err(|_| self.error(self.count_column, RleErrorKind::InvalidNumber(count)))
+    }
+
+    /// Parses a line of the pattern itself
+    fn parse_body(&mut self, line: &str) -> Result<(), RleError> {
+        for (column, c) in line.chars().enumerate().map(|(i, c)| (i + 1, c)) {
+            if c.is_whitespace() {
+                continue
+            }
+            self.body_started = true;
+            match c {
+                '0'..='9' => {
+                    if self.count.is_empty() {
+                        self.count_column = column;
+                    }
+                    self.count.push(c);
+                }
+                'o' => {
+                    for _ in 0..self.take_count()? {
+                        self.tree.insert(self.x, self.y, self.color);
+                        self.x += 1;
+                    }
+                }
+                'b' => self.x += self.take_count()?,
+                '$' => {
+                    self.y += self.take_count()?;
+                    self.x = self.origin_x;
+                }
+                'c' => {
+                    // A `c` tag without a number goes back to the default color
+                    let color = if self.count.is_empty() { 0 } else { self.take_count()? };
+                    if color as usize >= COLORS.len() {
+                        return Err(self.error(self.count_column, RleErrorKind::InvalidColor(color)))
+                    }
+                    self.color = color as usize;
+                }
+                '!' => {
+                    self.finished = true;
+                    return Ok(())
+                }
+                _ => return Err(self.error(column, RleErrorKind::UnexpectedChar(c))),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a whole RLE file
+pub fn parse(input: &str, mode: RleMode) -> Result<(RegionTree, RleSettings), RleError> {
+    let mut parser = RleParser::new(mode);
+    for line in input.lines() {
+        parser.parse_line(line)?;
+        if parser.is_finished() {
+            break
+        }
+    }
+    Ok(parser.finish())
+}
+
+/// Reads an RLE file from `reader`, until its closing `!` or the end of the input
+pub fn read<R: BufRead>(reader: R, mode: RleMode) -> Result<(RegionTree, RleSettings), RleError> {
+    let mut parser = RleParser::new(mode);
+    for line in reader.lines() {
+        let line = line.map_err(|err| RleError {
+            line: parser.line + 1,
+            column: None,
+            kind: RleErrorKind::Io(err.to_string()),
+        })?;
+        parser.parse_line(&line)?;
+        if parser.is_finished() {
+            break
+        }
+    }
+    Ok(parser.finish())
+}
+
+/// Writes a `RegionTree` as RLE, such that parsing it back in the same mode gives the same cells, colors, rule, topology and generation.
+/// The generation is always written, as the phase of the partition depends on it
+#[derive(Debug, Clone)]
+pub struct RleWriter {
+    /// Flavour of RLE to write
+    pub mode: RleMode,
+    /// Display parameters, only written if they differ from the defaults
+    pub settings: RleSettings,
+    /// Maximum length of the lines of the pattern
+    pub line_width: usize,
+}
+
+impl RleWriter {
+    /// Creates a writer for `mode`, with the default settings and line width
+    pub fn new(mode: RleMode) -> Self {
+        Self {
+            mode,
+            settings: RleSettings::default(),
+            line_width: 70,
+        }
+    }
+
+    /// Writes the header and pattern of `tree` into `writer`
+    pub fn write<W: Write>(&self, tree: &RegionTree, writer: &mut W) -> io::Result<()> {
+        let mut cells = tree.particles().map(|(_, (x, y), color)| (y, x, color)).collect::<Vec<_>>();
+        cells.sort_unstable();
+        let left = cells.iter().map(|&(_, x, _)| x).min().unwrap_or(0);
+        let right = cells.iter().map(|&(_, x, _)| x).max().unwrap_or(-1);
+        let top = cells.first().map(|&(y, _, _)| y).unwrap_or(0);
+        let bottom = cells.last().map(|&(y, _, _)| y).unwrap_or(-1);
+
+        let mut header = match self.mode {
+            RleMode::Legacy => vec![format!("x = {}", left), format!("y = {}", top), format!("gen = {}", tree.step)],
+            RleMode::Standard => {
+                writeln!(writer, "#CXRLE Pos={},{} Gen={}", left, top, tree.step)?;
+                vec![format!("x = {}", right - left + 1), format!("y = {}", bottom - top + 1)]
+            }
+        };
+        if self.settings != RleSettings::default() {
+            header.push(format!("s = {}", self.settings.steps));
+            header.push(format!("i = {}", self.settings.interval));
+            header.push(format!("u = {}", self.settings.smoothing));
+        }
+        if tree.rule != BlockRule::default() {
+            header.push(format!("rule = {}", tree.rule));
+        }
+        if tree.topology != Topology::Plane {
+            header.push(format!("topology = {}", tree.topology));
+        }
+        writeln!(writer, "{}", header.join(", "))?;
+
+        let run = |count: i64, tag: char| if count == 1 { tag.to_string() } else { format!("{}{}", count, tag) };
+        let mut tokens = Vec::new();
+        let (mut x, mut y, mut color) = (left, top, 0);
+        let mut i = 0;
+        while i < cells.len() {
+            let (cell_y, cell_x, cell_color) = cells[i];
+            if cell_y > y {
+                tokens.push(run(cell_y - y, '$'));
+                y = cell_y;
+                x = left;
+            }
+            if cell_x > x {
+                tokens.push(run(cell_x - x, 'b'));
+            }
+            if cell_color != color {
+                tokens.push(if cell_color == 0 { String::from("c") } else { format!("{}c", cell_color) });
+                color = cell_color;
+            }
+            let mut length = 1;
+            while i + length < cells.len() && cells[i + length] == (cell_y, cell_x + length as i64, cell_color) {
+                length += 1;
+            }
+            tokens.push(run(length as i64, 'o'));
+            x = cell_x + length as i64;
+            i += length;
+        }
+        tokens.push(String::from("!"));
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + token.len() > self.line_width {
+                writeln!(writer, "{}", line)?;
+                line.clear();
+            }
+            line.push_str(&token);
+        }
+        writeln!(writer, "{}", line)
+    }
+
+    /// Returns the RLE representation of `tree`
+    pub fn to_rle(&self, tree: &RegionTree) -> String {
+        let mut res = Vec::new();
+        self.write(tree, &mut res).expect("writing to a Vec can't fail");
+        String::from_utf8(res).expect("RLE files are written in ASCII")
+    }
+}
+
+#[test]
+fn test_rle_parse() {
+    // Each header line moves the pattern in legacy mode
+    let (tree, settings) = parse("x = -15, y = 0, s = 1, i = 25, u = 4\n2co1co2$2o\nx = 15, y = -3\n4co3co2$2o!", RleMode::Legacy).unwrap();
+    assert_eq!(settings, RleSettings { steps: 1, interval: 25, smoothing: 4 });
+    assert_eq!(tree.population(), 8);
+    assert!(tree.get(-15, 0) > 0 && tree.get(-14, 2) > 0 && tree.get(16, -3) > 0 && tree.get(16, -1) > 0);
+    assert_eq!(tree.colors[tree.get(15, -3)], 4);
+
+    let (tree, _) = parse("#N test\n#CXRLE Pos=-2,5 Gen=7\nx = 3, y = 2, rule = critters\nob\n$2o!", RleMode::Standard).unwrap();
+    assert_eq!(tree.rule, BlockRule::critters());
+    assert_eq!(tree.step, 7);
+    assert_eq!(parse("x = 0, y = 0, g = 3\no!", RleMode::Legacy).unwrap().0.step, 3);
+    assert!(tree.get(-2, 5) > 0 && tree.get(-2, 6) > 0 && tree.get(-1, 6) > 0);
+
+    let error = |input: &str, mode| parse(input, mode).unwrap_err();
+    assert_eq!(error("x = 0\n2o3k!", RleMode::Legacy), RleError { line: 2, column: Some(4), kind: RleErrorKind::UnexpectedChar('k') });
+    assert_eq!(error("x = 0, z = 1", RleMode::Legacy).kind, RleErrorKind::UnknownKey(String::from("z")));
+    assert_eq!(error("x = 0, y = a", RleMode::Legacy).column, Some(12));
+    assert_eq!(error("99999999999999999999o!", RleMode::Legacy).column, Some(1));
+    assert_eq!(error("o\nx = 1", RleMode::Standard).kind, RleErrorKind::HeaderAfterBody);
+    assert_eq!(error("x = 0\n2o12co!", RleMode::Legacy), RleError { line: 2, column: Some(3), kind: RleErrorKind::InvalidColor(12) });
+}
+
+#[test]
+fn test_rle_read_error() {
+    // Errors of the input itself have no column
+    let error = read(&[b'o', 0xff, b'!'][..], RleMode::Legacy).unwrap_err();
+    assert_eq!(error.line, 1);
+    assert_eq!(error.column, None);
+    assert!(matches!(error.kind, RleErrorKind::Io(_)));
+    assert!(error.to_string().starts_with("line 1: "));
+}
+
+#[test]
+fn test_rle_write() {
+    let mut tree = RegionTree::with_rule("M0,2,4,6,8,10,12,14,1,3,5,7,9,11,13,15".parse().unwrap());
+    tree.set_topology(Topology::Torus { width: 64, height: 32 });
+    tree.step = 5;
+    for (i, &(x, y)) in [(-3, 1), (-2, 1), (-1, 1), (40, 1), (0, 4), (1, 4)].iter().enumerate() {
+        tree.insert(x, y, i % 3);
+    }
+    let cells = |tree: &RegionTree| {
+        let mut cells = tree.particles().map(|(_, pos, color)| (pos, color)).collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    };
+
+    for &mode in &[RleMode::Legacy, RleMode::Standard] {
+        let mut writer = RleWriter::new(mode);
+        writer.line_width = 8;
+        let rle = writer.to_rle(&tree);
+        let (parsed, _) = parse(&rle, mode).unwrap();
+        assert_eq!(cells(&parsed), cells(&tree));
+        assert_eq!(parsed.rule.table, tree.rule.table);
+        assert_eq!(parsed.topology, tree.topology);
+        assert_eq!(parsed.step, tree.step);
+    }
+}
@@ -0,0 +1,479 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The contents of a 2x2 square are described by 4 slots, in clockwise order:
+/// slot 0 is the top-left cell, slot 1 the top-right cell, slot 2 the bottom-right cell and slot 3 the bottom-left cell.
+///
+/// A permutation tells, for each new slot, which of the old slots it takes its content from.
+pub type Permutation = [u8; 4];
+
+/// The permutation leaving a square untouched
+pub const IDENTITY: Permutation = [0, 1, 2, 3];
+/// Rotates the contents of a square clockwise
+pub const CLOCKWISE: Permutation = [3, 0, 1, 2];
+/// Rotates the contents of a square by 180°
+pub const HALF_TURN: Permutation = [2, 3, 0, 1];
+/// Rotates the contents of a square counter-clockwise
+pub const COUNTER_CLOCKWISE: Permutation = [1, 2, 3, 0];
+
+/// A reversible, particle-conserving rule on the Margolus neighborhood.
+///
+/// For each of the two phases of the partition, the rule holds a 16-entry lookup table
+/// indexed by the occupancy mask of a square (bit `i` is set if slot `i` holds a particle),
+/// whose entries are the permutation applied to the particles of that square.
+/// Because the particles are only ever permuted, their identity can be tracked across generations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRule {
+    /// Name of the rule, as written in RLE headers
+    pub name: String,
+    /// Tables of the even and odd phases
+    pub table: [[Permutation; 16]; 2],
+}
+
+/// Errors that can arise when building or parsing a `BlockRule`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleError {
+    /// An entry of the table isn't a permutation of the four slots
+    NotAPermutation {
+        /// Phase of the offending table
+        phase: usize,
+        /// Occupancy mask of the offending entry
+        mask: u8,
+    },
+    /// Two occupancy masks of the same phase map to the same mask, so the rule can't be reversed
+    NotReversible {
+        /// Phase of the offending table
+        phase: usize,
+    },
+    /// The output mask doesn't hold as many particles as the input mask
+    NotConserving {
+        /// Phase of the offending table
+        phase: usize,
+        /// Occupancy mask of the offending entry
+        mask: u8,
+    },
+    /// The rule string couldn't be understood
+    Syntax(String),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::NotAPermutation { phase, mask } => {
+                write!(f, "entry {} of phase {} is not a permutation of the four cells", mask, phase)
+            }
+            RuleError::NotReversible { phase } => write!(f, "phase {} of the rule is not reversible", phase),
+            RuleError::NotConserving { phase, mask } => {
+                write!(f, "entry {} of phase {} does not conserve the number of particles", mask, phase)
+            }
+            RuleError::Syntax(msg) => write!(f, "invalid rule string: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+/// Applies `permutation` to an occupancy mask
+pub fn permute_mask(mask: u8, permutation: Permutation) -> u8 {
+    let mut res = 0;
+    for (i, &from) in permutation.iter().enumerate() {
+        res |= ((mask >> from) & 1) << i;
+    }
+    res
+}
+
+/// Applies `permutation` to the contents of a square
+#[inline]
+pub fn permute<T: Copy>(square: [T; 4], permutation: Permutation) -> [T; 4] {
+    [
+        square[permutation[0] as usize],
+        square[permutation[1] as usize],
+        square[permutation[2] as usize],
+        square[permutation[3] as usize],
+    ]
+}
+
+/// Builds a table where every mask with `n` particles, for which `apply[n]` is true, is permuted by `permutation`
+fn table_by_count(apply: [bool; 5], permutation: Permutation) -> [Permutation; 16] {
+    let mut table = [IDENTITY; 16];
+    for (mask, entry) in table.iter_mut().enumerate() {
+        if apply[mask.count_ones() as usize] {
+            *entry = permutation;
+        }
+    }
+    table
+}
+
+/// A rotation or reflection of the plane which maps the squares of the partition onto squares of the same phase.
+/// It is applied in order: the coordinates are first swapped if `swap` is true, then each axis is mirrored
+/// around `-1/2` if `flip_x` (respectively `flip_y`) is true.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symmetry {
+    /// Whether the coordinates are swapped
+    pub swap: bool,
+    /// Whether the horizontal axis is mirrored
+    pub flip_x: bool,
+    /// Whether the vertical axis is mirrored
+    pub flip_y: bool,
+}
+
+impl Symmetry {
+    /// The eight rotations and reflections of the square, starting with the identity
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry { swap: false, flip_x: false, flip_y: false },
+        Symmetry { swap: false, flip_x: true, flip_y: false },
+        Symmetry { swap: false, flip_x: false, flip_y: true },
+        Symmetry { swap: false, flip_x: true, flip_y: true },
+        Symmetry { swap: true, flip_x: false, flip_y: false },
+        Symmetry { swap: true, flip_x: true, flip_y: false },
+        Symmetry { swap: true, flip_x: false, flip_y: true },
+        Symmetry { swap: true, flip_x: true, flip_y: true },
+    ];
+
+    /// Applies the symmetry to a cell
+    pub fn apply(&self, x: i64, y: i64) -> (i64, i64) {
+        let (x, y) = if self.swap { (y, x) } else { (x, y) };
+        (
+            if self.flip_x { -1 - x } else { x },
+            if self.flip_y { -1 - y } else { y },
+        )
+    }
+
+    /// Applies the symmetry to the occupancy mask of a square
+    pub fn apply_mask(&self, mask: u8) -> u8 {
+        const SLOTS: [(i64, i64); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+        let mut res = 0;
+        for (slot, &(x, y)) in SLOTS.iter().enumerate() {
+            if (mask >> slot) & 1 == 1 {
+                let (x, y) = self.apply(x, y);
+                let target = (x.rem_euclid(2), y.rem_euclid(2));
+                res |= 1 << SLOTS.iter().position(|&pos| pos == target).unwrap();
+            }
+        }
+        res
+    }
+}
+
+impl BlockRule {
+    /// Creates a new rule from the tables of the even and odd phases, verifying that it is reversible
+    pub fn new(name: impl Into<String>, even: [Permutation; 16], odd: [Permutation; 16]) -> Result<Self, RuleError> {
+        let table = [even, odd];
+        for (phase, table) in table.iter().enumerate() {
+            let mut seen = [false; 16];
+            for (mask, permutation) in table.iter().enumerate() {
+                let mut sorted = *permutation;
+                sorted.sort_unstable();
+                if sorted != IDENTITY {
+                    return Err(RuleError::NotAPermutation { phase, mask: mask as u8 })
+                }
+                let output = permute_mask(mask as u8, *permutation);
+                if seen[output as usize] {
+                    return Err(RuleError::NotReversible { phase })
+                }
+                seen[output as usize] = true;
+            }
+        }
+
+        Ok(Self {
+            name: name.into(),
+            table,
+        })
+    }
+
+    /// Creates a rule from the output masks of each phase, as used in the `M` rule notation.
+    /// The particles of a square are moved along a rotation or a reflection of the square whenever possible.
+    pub fn from_masks(name: impl Into<String>, even: [u8; 16], odd: [u8; 16]) -> Result<Self, RuleError> {
+        const CANDIDATES: [Permutation; 8] = [
+            IDENTITY,
+            CLOCKWISE,
+            HALF_TURN,
+            COUNTER_CLOCKWISE,
+            [1, 0, 3, 2],
+            [3, 2, 1, 0],
+            [0, 3, 2, 1],
+            [2, 1, 0, 3],
+        ];
+
+        let mut tables = [[IDENTITY; 16]; 2];
+        for (phase, masks) in [even, odd].iter().enumerate() {
+            for (mask, &output) in masks.iter().enumerate() {
+                let mask = mask as u8;
+                if output >= 16 || mask.count_ones() != output.count_ones() {
+                    return Err(RuleError::NotConserving { phase, mask })
+                }
+                tables[phase][mask as usize] = match CANDIDATES.iter().find(|&&p| permute_mask(mask, p) == output) {
+                    Some(&permutation) => permutation,
+                    None => {
+                        // Match the particles in slot order
+                        let mut permutation = [0; 4];
+                        let mut empty = (0..4).filter(|i| (mask >> i) & 1 == 0);
+                        let mut full = (0..4).filter(|i| (mask >> i) & 1 == 1);
+                        for (i, slot) in permutation.iter_mut().enumerate() {
+                            *slot = if (output >> i) & 1 == 1 {
+                                full.next().unwrap()
+                            } else {
+                                empty.next().unwrap()
+                            };
+                        }
+                        permutation
+                    }
+                };
+            }
+        }
+
+        Self::new(name, tables[0], tables[1])
+    }
+
+    /// The "single rotation" rule: squares holding exactly one particle are rotated clockwise
+    pub fn single_rotation() -> Self {
+        let table = table_by_count([false, true, false, false, false], CLOCKWISE);
+        Self::new("single-rotation", table, table).unwrap()
+    }
+
+    /// The "double rotation" rule: squares holding one or two particles are rotated clockwise
+    pub fn double_rotation() -> Self {
+        let table = table_by_count([false, true, true, false, false], CLOCKWISE);
+        Self::new("double-rotation", table, table).unwrap()
+    }
+
+    /// The billiard ball machine: single particles cross the square diagonally,
+    /// and two particles colliding head-on are deflected by 90°
+    pub fn billiard_ball() -> Self {
+        let mut table = table_by_count([false, true, false, false, false], HALF_TURN);
+        table[0b0101] = CLOCKWISE;
+        table[0b1010] = CLOCKWISE;
+        Self::new("billiard-ball", table, table).unwrap()
+    }
+
+    /// The "critters" rule, expressed in its particle-conserving form:
+    /// the original rule complements the squares without exactly two live cells, and turns the ones that had three by 180°.
+    /// Particles are the live cells on even generations and the dead cells on odd ones, which makes the phases differ:
+    /// three particles turn on even generations, a single particle turns on odd ones, and pairs move to the two other cells.
+    pub fn critters() -> Self {
+        let mut even = table_by_count([false, false, false, true, false], HALF_TURN);
+        let mut odd = table_by_count([false, true, false, false, false], HALF_TURN);
+        for table in [&mut even, &mut odd] {
+            // The complement of two adjacent particles is the opposite pair
+            for &mask in &[0b0011, 0b0110, 0b1100, 0b1001] {
+                table[mask] = HALF_TURN;
+            }
+            // The complement of two diagonal particles is the other diagonal
+            table[0b0101] = CLOCKWISE;
+            table[0b1010] = CLOCKWISE;
+        }
+        Self::new("critters", even, odd).unwrap()
+    }
+
+    /// Returns true if both phases of the partition follow the same table
+    pub fn is_phase_symmetric(&self) -> bool {
+        self.table[0] == self.table[1]
+    }
+
+    /// Returns the symmetries of the plane that commute with the rule: transforming a pattern and then stepping it
+    /// gives the same cells as stepping it and then transforming it. The identity always comes first.
+    pub fn symmetries(&self) -> Vec<Symmetry> {
+        Symmetry::ALL
+            .iter()
+            .copied()
+            .filter(|symmetry| {
+                self.table.iter().all(|table| {
+                    (0..16u8).all(|mask| {
+                        let transformed = symmetry.apply_mask(mask);
+                        permute_mask(transformed, table[transformed as usize])
+                            == symmetry.apply_mask(permute_mask(mask, table[mask as usize]))
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the rule undoing this one, such that stepping with both rules in the same phase does nothing
+    pub fn inverse(&self) -> Self {
+        let mut table = [[IDENTITY; 16]; 2];
+        for (inverse_table, forward_table) in table.iter_mut().zip(self.table.iter()) {
+            for (mask, &permutation) in forward_table.iter().enumerate() {
+                let output = permute_mask(mask as u8, permutation);
+                let mut inverse = [0; 4];
+                for (i, &from) in permutation.iter().enumerate() {
+                    inverse[from as usize] = i as u8;
+                }
+                inverse_table[output as usize] = inverse;
+            }
+        }
+
+        Self {
+            name: format!("{}-inverse", self.name),
+            table,
+        }
+    }
+}
+
+impl Default for BlockRule {
+    fn default() -> Self {
+        Self::single_rotation()
+    }
+}
+
+impl fmt::Display for BlockRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if BUILTIN.iter().any(|(name, _)| *name == self.name) {
+            return write!(f, "{}", self.name)
+        }
+
+        // Fall back to the M notation
+        write!(f, "M")?;
+        for (phase, table) in self.table.iter().enumerate() {
+            if phase == 1 {
+                write!(f, "/")?;
+            }
+            for (mask, &permutation) in table.iter().enumerate() {
+                if mask > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}", permute_mask(mask as u8, permutation))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A function building one of the built-in rules
+type Builder = fn() -> BlockRule;
+
+/// The named, built-in rules
+const BUILTIN: [(&str, Builder); 4] = [
+    ("single-rotation", BlockRule::single_rotation),
+    ("double-rotation", BlockRule::double_rotation),
+    ("billiard-ball", BlockRule::billiard_ball),
+    ("critters", BlockRule::critters),
+];
+
+impl FromStr for BlockRule {
+    type Err = RuleError;
+
+    /// Parses either the name of a built-in rule (`single-rotation`, `double-rotation`, `billiard-ball` or `critters`),
+    /// or a rule in the `M` notation: `M` followed by the 16 output masks of the even phase, separated by commas,
+    /// optionally followed by `/` and the 16 output masks of the odd phase.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let lowercase = s.to_ascii_lowercase();
+        if let Some((_, builder)) = BUILTIN.iter().find(|(name, _)| *name == lowercase) {
+            return Ok(builder())
+        }
+        if lowercase == "tron" {
+            return Err(RuleError::Syntax(String::from("tron does not conserve particles and can't be simulated")))
+        }
+
+        let body = match s.strip_prefix('M').or_else(|| s.strip_prefix('m')) {
+            Some(body) => body,
+            None => return Err(RuleError::Syntax(format!("unknown rule {:?}", s))),
+        };
+
+        let mut phases = Vec::new();
+        for phase in body.split('/') {
+            let mut masks = [0; 16];
+            let entries = phase
+                .split(',')
+                .map(|entry| entry.trim().parse::<u8>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| RuleError::Syntax(err.to_string()))?;
+            if entries.len() != 16 {
+                return Err(RuleError::Syntax(format!("expected 16 entries, got {}", entries.len())))
+            }
+            masks.copy_from_slice(&entries);
+            phases.push(masks);
+        }
+
+        match phases.len() {
+            1 => Self::from_masks(s, phases[0], phases[0]),
+            2 => Self::from_masks(s, phases[0], phases[1]),
+            _ => Err(RuleError::Syntax(String::from("expected at most two phases"))),
+        }
+    }
+}
+
+#[test]
+fn test_rule_inverse() {
+    for (_, builder) in BUILTIN.iter() {
+        let rule = builder();
+        let inverse = rule.inverse();
+        for phase in 0..2 {
+            for mask in 0..16u8 {
+                let square = [1, 2, 4, 8].map(|id| if (mask as usize & id) > 0 { id } else { 0 });
+                let forward = permute(square, rule.table[phase][mask as usize]);
+                let output = permute_mask(mask, rule.table[phase][mask as usize]);
+                assert_eq!(permute(forward, inverse.table[phase][output as usize]), square);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rule_parse() {
+    assert_eq!("critters".parse::<BlockRule>(), Ok(BlockRule::critters()));
+    let rule = BlockRule::single_rotation();
+    let parsed = rule.to_string().parse::<BlockRule>().unwrap();
+    assert_eq!(parsed.table, rule.table);
+    let custom = "M0,2,4,6,8,10,12,14,1,3,5,7,9,11,13,15".parse::<BlockRule>().unwrap();
+    assert_eq!(custom.to_string().parse::<BlockRule>().unwrap().table, custom.table);
+    assert!("M0,1,2".parse::<BlockRule>().is_err());
+    assert!("tron".parse::<BlockRule>().is_err());
+}
+
+#[test]
+fn test_rule_symmetries() {
+    // Rotating particles clockwise can't be mirrored
+    assert_eq!(BlockRule::single_rotation().symmetries().len(), 4);
+    assert_eq!(BlockRule::billiard_ball().symmetries().len(), 8);
+    assert_eq!(BlockRule::critters().symmetries()[0], Symmetry::ALL[0]);
+}
+
+#[test]
+fn test_critters() {
+    use super::regions::{RegionTree, Topology};
+
+    let (width, height) = (16, 12);
+    let mut seed = 4242u64;
+    for _ in 0..4 {
+        let mut tree = RegionTree::with_rule(BlockRule::critters());
+        tree.set_topology(Topology::Torus { width, height });
+        let mut grid = vec![false; (width * height) as usize];
+        for (i, cell) in grid.iter_mut().enumerate() {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            if seed.is_multiple_of(3) {
+                *cell = true;
+                tree.insert(i as i64 % width, i as i64 / width, 0);
+            }
+        }
+
+        // Naive Critters: squares without exactly two live cells are complemented, and those that had three are turned by 180°
+        for step in 0..40 {
+            let phase = step % 2;
+            for y in (phase..height).step_by(2) {
+                for x in (phase..width).step_by(2) {
+                    let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)]
+                        .map(|(x, y)| (x.rem_euclid(width) + y.rem_euclid(height) * width) as usize);
+                    let square = corners.map(|i| grid[i]);
+                    let count = square.iter().filter(|&&cell| cell).count();
+                    let square = match count {
+                        2 => square,
+                        3 => permute(square, HALF_TURN).map(|cell| !cell),
+                        _ => square.map(|cell| !cell),
+                    };
+                    for (&i, &cell) in corners.iter().zip(square.iter()) {
+                        grid[i] = cell;
+                    }
+                }
+            }
+            tree.tick();
+
+            // The particles are the live cells on even generations, and the dead cells on odd ones
+            for (i, &cell) in grid.iter().enumerate() {
+                let particle = tree.get(i as i64 % width, i as i64 / width) > 0;
+                assert_eq!(particle, cell == tree.step.is_multiple_of(2));
+            }
+        }
+    }
+}
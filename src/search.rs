@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use super::census::{census, Census};
+use super::regions::RegionTree;
+use super::rules::BlockRule;
+
+/// The symmetry imposed on a random soup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SoupSymmetry {
+    /// No symmetry
+    #[default]
+    C1,
+    /// Symmetric under a rotation by 180°
+    C2,
+    /// Symmetric under a rotation by 90°
+    C4,
+    /// Symmetric under a reflection across the vertical axis
+    D2,
+}
+
+impl SoupSymmetry {
+    /// Returns the images of a cell of a soup of side `size`, including itself
+    fn images(&self, x: i64, y: i64, size: i64) -> Vec<(i64, i64)> {
+        let m = size - 1;
+        match self {
+            SoupSymmetry::C1 => vec![(x, y)],
+            SoupSymmetry::C2 => vec![(x, y), (m - x, m - y)],
+            SoupSymmetry::C4 => vec![(x, y), (m - y, x), (m - x, m - y), (y, m - x)],
+            SoupSymmetry::D2 => vec![(x, y), (m - x, y)],
+        }
+    }
+}
+
+impl fmt::Display for SoupSymmetry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SoupSymmetry::C1 => write!(f, "C1"),
+            SoupSymmetry::C2 => write!(f, "C2"),
+            SoupSymmetry::C4 => write!(f, "C4"),
+            SoupSymmetry::D2 => write!(f, "D2"),
+        }
+    }
+}
+
+impl FromStr for SoupSymmetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "C1" | "c1" => Ok(SoupSymmetry::C1),
+            "C2" | "c2" => Ok(SoupSymmetry::C2),
+            "C4" | "c4" => Ok(SoupSymmetry::C4),
+            "D2" | "d2" => Ok(SoupSymmetry::D2),
+            _ => Err(format!("Unknown soup symmetry: {:?}", s)),
+        }
+    }
+}
+
+/// Parameters of a soup search
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    /// Rule the soups are run with
+    pub rule: BlockRule,
+    /// Side of the square of random cells; rounded up to an even number so that the symmetries respect the partition
+    pub size: usize,
+    /// Probability for each cell of the soup to be alive, between 0 and 1
+    pub density: f64,
+    /// Symmetry imposed on the soups
+    pub symmetry: SoupSymmetry,
+    /// Maximum number of generations to run each soup for
+    pub max_generations: usize,
+    /// Number of generations between two checks for stabilization
+    pub check_interval: usize,
+    /// Maximum number of generations to run each object for when identifying it
+    pub max_period: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            rule: BlockRule::default(),
+            size: 16,
+            density: 0.5,
+            symmetry: SoupSymmetry::C1,
+            max_generations: 10000,
+            check_interval: 100,
+            max_period: 200,
+        }
+    }
+}
+
+impl fmt::Display for SearchConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rule = {}, size = {}, density = {}, symmetry = {}, max_generations = {}, check_interval = {}, max_period = {}",
+            self.rule, self.size, self.density, self.symmetry, self.max_generations, self.check_interval, self.max_period
+        )
+    }
+}
+
+/// Why the run of a soup ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every object of the soup could be identified
+    Stabilized,
+    /// Every cell left the bounding box of the soup
+    Escaped,
+    /// The soup was still active after `max_generations`
+    Timeout,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Stabilized => write!(f, "stabilized"),
+            Outcome::Escaped => write!(f, "escaped"),
+            Outcome::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+/// The result of running a single soup
+#[derive(Debug, Clone)]
+pub struct SoupResult {
+    /// Seed of the soup, which can be given to `soup` to reproduce it
+    pub seed: u64,
+    /// What the soup settled into
+    pub outcome: Outcome,
+    /// Number of generations the soup was run for
+    pub generations: usize,
+    /// Objects left once the soup settled
+    pub census: Census,
+}
+
+/// The combined results of a soup search
+#[derive(Debug, Clone, Default)]
+pub struct SearchSummary {
+    /// Parameters the soups were run with, written in the header so that any soup can be reproduced from the output
+    pub config: SearchConfig,
+    /// Result of each soup, in the order of their seeds
+    pub results: Vec<SoupResult>,
+    /// Total number of objects of each kind, indexed by their code
+    pub totals: BTreeMap<String, usize>,
+    /// Seed of the first soup in which each kind of object was found, indexed by their code
+    pub first_seeds: BTreeMap<String, u64>,
+}
+
+impl SearchSummary {
+    /// Adds the result of a soup to the summary
+    pub fn add(&mut self, result: SoupResult) {
+        for (code, &count) in result.census.counts.iter() {
+            *self.totals.entry(code.clone()).or_insert(0) += count;
+            self.first_seeds.entry(code.clone()).or_insert(result.seed);
+        }
+        self.results.push(result);
+    }
+}
+
+impl fmt::Display for SearchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# {}", self.config)?;
+        writeln!(f, "# seed\toutcome\tgenerations\tobjects\tunclassified")?;
+        for result in &self.results {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{}",
+                result.seed,
+                result.outcome,
+                result.generations,
+                result.census.objects.len(),
+                result.census.n_unclassified()
+            )?;
+        }
+
+        let mut totals = self.totals.iter().collect::<Vec<_>>();
+        totals.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        writeln!(f, "# count\tcode\tfirst seed")?;
+        for (code, count) in totals {
+            writeln!(f, "{}\t{}\t{}", count, code, self.first_seeds[code])?;
+        }
+        Ok(())
+    }
+}
+
+/// Generates the soup of seed `seed`: a `size`x`size` square starting at `(0, 0)`, where each cell is alive with a probability of `density`.
+/// The same seed and parameters always give the same soup: the cells are drawn from ChaCha8, whose output is fixed across releases of `rand`.
+///
+/// Panics if `config.density` isn't between 0 and 1.
+pub fn soup(config: &SearchConfig, seed: u64) -> RegionTree {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let size = (config.size + config.size % 2) as i64;
+    let mut tree = RegionTree::with_rule(config.rule.clone());
+
+    for y in 0..size {
+        for x in 0..size {
+            if rng.gen_bool(config.density) {
+                for (x, y) in config.symmetry.images(x, y, size) {
+                    if tree.get(x, y) == 0 {
+                        tree.insert(x, y, 0);
+                    }
+                }
+            }
+        }
+    }
+
+    tree
+}
+
+/// Runs the soup of seed `seed` until all of its objects can be identified, until it leaves its bounding box or
+/// for at most `config.max_generations`, and takes a census of what remains
+pub fn run_soup(config: &SearchConfig, seed: u64) -> SoupResult {
+    let mut tree = soup(config, seed);
+    let size = (config.size + config.size % 2) as i64;
+    let check_interval = config.check_interval.max(1);
+    let mut outcome = Outcome::Timeout;
+    let mut result = None;
+
+    while tree.step < config.max_generations {
+        for _ in 0..check_interval.min(config.max_generations - tree.step) {
+            tree.tick();
+        }
+
+        let inside = tree.particles().any(|(_, (x, y), _)| x >= 0 && x < size && y >= 0 && y < size);
+        if !inside {
+            outcome = Outcome::Escaped;
+            break
+        }
+        let objects = census(&tree, config.max_period);
+        if objects.n_unclassified() == 0 {
+            outcome = Outcome::Stabilized;
+            result = Some(objects);
+            break
+        }
+    }
+
+    SoupResult {
+        seed,
+        outcome,
+        generations: tree.step,
+        census: result.unwrap_or_else(|| census(&tree, config.max_period)),
+    }
+}
+
+/// Runs `count` soups, with seeds starting at `first_seed`
+pub fn search(config: &SearchConfig, first_seed: u64, count: usize) -> SearchSummary {
+    let mut summary = SearchSummary {
+        config: config.clone(),
+        ..SearchSummary::default()
+    };
+    for seed in first_seed..first_seed + count as u64 {
+        summary.add(run_soup(config, seed));
+    }
+    summary
+}
+
+#[test]
+fn test_soup_search() {
+    let config = SearchConfig {
+        size: 8,
+        symmetry: SoupSymmetry::C4,
+        max_generations: 400,
+        check_interval: 50,
+        max_period: 100,
+        ..SearchConfig::default()
+    };
+
+    // Soups are reproducible and symmetric
+    let cells = |tree: &RegionTree| {
+        let mut cells = tree.particles().map(|(_, pos, _)| pos).collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    };
+    let tree = soup(&config, 7);
+    assert_eq!(cells(&tree), cells(&soup(&config, 7)));
+    // The soup of a seed never changes, so that seeds recorded in earlier searches stay valid
+    let small = SearchConfig {
+        size: 4,
+        ..SearchConfig::default()
+    };
+    assert_eq!(
+        cells(&soup(&small, 1)),
+        vec![(0, 0), (0, 1), (0, 3), (1, 0), (1, 3), (2, 1), (2, 2), (2, 3), (3, 0), (3, 1), (3, 3)]
+    );
+    for (x, y) in cells(&tree) {
+        assert!(tree.get(7 - y, x) > 0);
+    }
+
+    let summary = search(&config, 0, 3);
+    assert_eq!(summary.results.len(), 3);
+    assert!(summary.to_string().starts_with("# rule = single-rotation, size = 8, density = 0.5, symmetry = C4, max_generations = 400, check_interval = 50, max_period = 100\n"));
+    for result in &summary.results {
+        let rerun = run_soup(&config, result.seed);
+        assert_eq!(rerun.generations, result.generations);
+        assert_eq!(rerun.census.counts, result.census.counts);
+    }
+}
@@ -0,0 +1,398 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use super::regions::{Region, RegionTree, Topology, REGION_SIZE};
+use super::rules::BlockRule;
+
+/// First bytes of every snapshot
+const MAGIC: &[u8; 8] = b"SROTSNAP";
+/// Version of the format written by `save`; `load` refuses any other version
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// Extension of the files written by `Checkpointer`
+const EXTENSION: &str = "snapshot";
+/// File of a checkpoint directory holding the generation the run stops at
+const TARGET_FILE: &str = "target";
+
+/// Errors that can arise when loading a snapshot
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The snapshot couldn't be read or written
+    Io(io::Error),
+    /// The data doesn't start with the snapshot header
+    NotASnapshot,
+    /// The snapshot was written by a different version of the format
+    UnsupportedVersion(u32),
+    /// The data was corrupted
+    Checksum {
+        /// Checksum stored in the snapshot
+        expected: u64,
+        /// Checksum of the data that was read
+        found: u64,
+    },
+    /// The data is well-formed, but doesn't describe a valid universe
+    Invalid(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "{}", err),
+            SnapshotError::NotASnapshot => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {} (expected {})", version, SNAPSHOT_VERSION)
+            }
+            SnapshotError::Checksum { expected, found } => {
+                write!(f, "corrupted snapshot: checksum is {:016x}, expected {:016x}", found, expected)
+            }
+            SnapshotError::Invalid(reason) => write!(f, "invalid snapshot: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+/// 64-bit FNV-1a hash, used as the checksum of snapshots
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_i64(buffer: &mut Vec<u8>, value: i64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Reads the fields of a snapshot in order
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < n {
+            return Err(SnapshotError::Invalid(String::from("unexpected end of data")))
+        }
+        let (res, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn i64(&mut self) -> Result<i64, SnapshotError> {
+        Ok(self.u64()? as i64)
+    }
+
+    /// Reads a length, making sure that it isn't larger than what is left to read
+    fn len(&mut self, item_size: usize) -> Result<usize, SnapshotError> {
+        let len = self.u64()? as usize;
+        if len.saturating_mul(item_size) > self.bytes.len() {
+            return Err(SnapshotError::Invalid(String::from("unexpected end of data")))
+        }
+        Ok(len)
+    }
+}
+
+/// Writes every field of `tree` that affects the simulation, so that `load` gives back a tree which evolves identically.
+///
+/// The snapshot is made of the `SROTSNAP` magic bytes, the version as a little-endian `u32`, the contents of the tree and
+/// the FNV-1a hash of everything before it; all numbers are written in little-endian order.
+pub fn save<W: Write>(tree: &RegionTree, writer: &mut W) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(MAGIC);
+    buffer.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+
+    put_u64(&mut buffer, tree.step as u64);
+    put_u64(&mut buffer, tree.rule.name.len() as u64);
+    buffer.extend_from_slice(tree.rule.name.as_bytes());
+    for table in tree.rule.table.iter() {
+        for permutation in table.iter() {
+            buffer.extend_from_slice(permutation);
+        }
+    }
+    let (kind, width, height) = match tree.topology {
+        Topology::Plane => (0, 0, 0),
+        Topology::Torus { width, height } => (1, width, height),
+        Topology::Box { width, height } => (2, width, height),
+    };
+    buffer.push(kind);
+    put_i64(&mut buffer, width);
+    put_i64(&mut buffer, height);
+
+    put_u64(&mut buffer, tree.cells.len() as u64);
+    for ((&(x, y), &color), &alive) in tree.cells.iter().zip(tree.colors.iter()).zip(tree.alive.iter()) {
+        put_i64(&mut buffer, x);
+        put_i64(&mut buffer, y);
+        put_u64(&mut buffer, color as u64);
+        buffer.push(alive as u8);
+    }
+
+    put_u64(&mut buffer, tree.regions.len() as u64);
+    for region in tree.regions.iter() {
+        put_i64(&mut buffer, region.x);
+        put_i64(&mut buffer, region.y);
+        put_u64(&mut buffer, region.n_cells as u64);
+        for neighbor in region.neighbors.iter() {
+            put_u64(&mut buffer, neighbor.map(|i| i as u64).unwrap_or(u64::MAX));
+        }
+        for &cell in region.cells.iter().flat_map(|row| row.iter()) {
+            put_u64(&mut buffer, cell as u64);
+        }
+    }
+
+    let checksum = fnv1a(&buffer);
+    put_u64(&mut buffer, checksum);
+    writer.write_all(&buffer)
+}
+
+/// Returns true if `bytes` start like a snapshot, which tells snapshots apart from RLE files
+pub fn is_snapshot(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Reads a snapshot written by `save`
+pub fn load<R: Read>(reader: &mut R) -> Result<RegionTree, SnapshotError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() < MAGIC.len() + 4 + 8 || !is_snapshot(&bytes) {
+        return Err(SnapshotError::NotASnapshot)
+    }
+    let mut version = [0; 4];
+    version.copy_from_slice(&bytes[MAGIC.len()..MAGIC.len() + 4]);
+    let version = u32::from_le_bytes(version);
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version))
+    }
+
+    let (contents, checksum) = bytes.split_at(bytes.len() - 8);
+    let mut expected = [0; 8];
+    expected.copy_from_slice(checksum);
+    let expected = u64::from_le_bytes(expected);
+    let found = fnv1a(contents);
+    if found != expected {
+        return Err(SnapshotError::Checksum { expected, found })
+    }
+
+    let mut cursor = Cursor { bytes: &contents[MAGIC.len() + 4..] };
+    let step = cursor.u64()? as usize;
+    let name_len = cursor.len(1)?;
+    let name = String::from_utf8(cursor.take(name_len)?.to_vec())
+        .map_err(|_| SnapshotError::Invalid(String::from("the name of the rule isn't valid UTF-8")))?;
+    let mut even = [[0; 4]; 16];
+    let mut odd = [[0; 4]; 16];
+    for permutation in even.iter_mut().chain(odd.iter_mut()) {
+        permutation.copy_from_slice(cursor.take(4)?);
+    }
+    let rule = BlockRule::new(name, even, odd).map_err(|err| SnapshotError::Invalid(err.to_string()))?;
+    let topology = match (cursor.u8()?, cursor.i64()?, cursor.i64()?) {
+        (0, _, _) => Topology::Plane,
+        (1, width, height) => Topology::Torus { width, height },
+        (2, width, height) => Topology::Box { width, height },
+        (kind, _, _) => return Err(SnapshotError::Invalid(format!("unknown topology {}", kind))),
+    };
+
+    let mut tree = RegionTree::with_rule(rule);
+    tree.step = step;
+    tree.topology = topology;
+    tree.cells.clear();
+    tree.colors.clear();
+    tree.alive.clear();
+    tree.regions.clear();
+    tree.hashmap.clear();
+
+    let n_cells = cursor.len(25)?;
+    for _ in 0..n_cells {
+        tree.cells.push((cursor.i64()?, cursor.i64()?));
+        tree.colors.push(cursor.u64()? as usize);
+        tree.alive.push(cursor.u8()? != 0);
+    }
+
+    let n_regions = cursor.len(8 * (11 + REGION_SIZE * REGION_SIZE))?;
+    for index in 0..n_regions {
+        let mut region = Region::new(cursor.i64()?, cursor.i64()?);
+        region.n_cells = cursor.u64()? as usize;
+        for neighbor in region.neighbors.iter_mut() {
+            *neighbor = match cursor.u64()? {
+                u64::MAX => None,
+                i if (i as usize) < n_regions => Some(i as usize),
+                _ => return Err(SnapshotError::Invalid(String::from("region neighbor out of bounds"))),
+            };
+        }
+        for cell in region.cells.iter_mut().flat_map(|row| row.iter_mut()) {
+            *cell = cursor.u64()? as usize;
+            if *cell >= n_cells {
+                return Err(SnapshotError::Invalid(String::from("particle id out of bounds")))
+            }
+        }
+        tree.hashmap.insert((region.x, region.y), index);
+        tree.regions.push(region);
+    }
+
+    Ok(tree)
+}
+
+/// Writes a snapshot of `tree` to `path`; the file is only replaced once the snapshot was fully written
+pub fn save_file(tree: &RegionTree, path: &Path) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = io::BufWriter::new(fs::File::create(&temporary)?);
+    save(tree, &mut file)?;
+    file.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+    fs::rename(temporary, path)
+}
+
+/// Reads the snapshot at `path`
+pub fn load_file(path: &Path) -> Result<RegionTree, SnapshotError> {
+    load(&mut io::BufReader::new(fs::File::open(path)?))
+}
+
+/// Periodically saves snapshots of a running simulation into a directory
+#[derive(Debug, Clone)]
+pub struct Checkpointer {
+    /// Directory the snapshots are saved in
+    pub directory: PathBuf,
+    /// Number of generations between two snapshots
+    pub interval: usize,
+    /// Number of snapshots to keep; older ones are deleted
+    pub keep: usize,
+    next: usize,
+}
+
+impl Checkpointer {
+    /// Creates `directory` if needed; the first snapshot will be taken `interval` generations after `step`
+    pub fn new(directory: impl Into<PathBuf>, interval: usize, step: usize) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        let interval = interval.max(1);
+        Ok(Self {
+            directory,
+            interval,
+            keep: 2,
+            next: step + interval,
+        })
+    }
+
+    /// Returns the generation at which the next snapshot will be taken
+    pub fn next_step(&self) -> usize {
+        self.next
+    }
+
+    /// Saves a snapshot of `tree` if it reached the next checkpoint, and returns its path
+    pub fn update(&mut self, tree: &RegionTree) -> io::Result<Option<PathBuf>> {
+        if tree.step < self.next {
+            return Ok(None)
+        }
+        self.next = tree.step + self.interval;
+
+        let path = self.directory.join(format!("{:020}.{}", tree.step, EXTENSION));
+        save_file(tree, &path)?;
+
+        let snapshots = list_snapshots(&self.directory)?;
+        for old in snapshots.iter().take(snapshots.len().saturating_sub(self.keep)) {
+            fs::remove_file(old)?;
+        }
+        Ok(Some(path))
+    }
+}
+
+/// Returns the snapshots written by `Checkpointer` in `directory`, the oldest first
+fn list_snapshots(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut res = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map(|extension| extension == EXTENSION).unwrap_or(false))
+        .collect::<Vec<_>>();
+    // Steps are zero-padded, so that their names sort in the same order
+    res.sort();
+    Ok(res)
+}
+
+/// Records in `directory` the generation at which the run saving snapshots into it stops, so that resumed runs stop at the same generation;
+/// `None` removes the previous record
+pub fn save_target(directory: &Path, generation: Option<usize>) -> io::Result<()> {
+    let path = directory.join(TARGET_FILE);
+    match generation {
+        Some(generation) => fs::write(path, format!("{}\n", generation)),
+        None => match fs::remove_file(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        },
+    }
+}
+
+/// Returns the generation recorded by `save_target` in `directory`, if there is one
+pub fn load_target(directory: &Path) -> io::Result<Option<usize>> {
+    match fs::read_to_string(directory.join(TARGET_FILE)) {
+        Ok(content) => content
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid target generation {:?}", content.trim()))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the latest snapshot written by `Checkpointer` in `directory`
+pub fn latest(directory: &Path) -> io::Result<Option<PathBuf>> {
+    Ok(list_snapshots(directory)?.pop())
+}
+
+#[test]
+fn test_snapshot() {
+    let mut tree = RegionTree::with_rule(BlockRule::critters());
+    for i in 0..60 {
+        tree.insert((i * 7) % 40 - 20, (i * 13) % 30 - 15, i as usize % 3);
+    }
+    tree.remove(1, -2);
+    for _ in 0..31 {
+        tree.tick();
+    }
+
+    let mut bytes = Vec::new();
+    save(&tree, &mut bytes).unwrap();
+    let mut loaded = load(&mut bytes.as_slice()).unwrap();
+    assert_eq!(loaded.step, tree.step);
+    assert_eq!(loaded.colors, tree.colors);
+    for _ in 0..100 {
+        tree.tick();
+        loaded.tick();
+    }
+    assert_eq!(loaded.cells, tree.cells);
+    assert_eq!(loaded.alive, tree.alive);
+
+    bytes[20] ^= 1;
+    assert!(matches!(load(&mut bytes.as_slice()), Err(SnapshotError::Checksum { .. })));
+    bytes[8] = 2;
+    assert!(matches!(load(&mut bytes.as_slice()), Err(SnapshotError::UnsupportedVersion(_))));
+    assert!(matches!(load(&mut &b"hello"[..]), Err(SnapshotError::NotASnapshot)));
+
+    let directory = std::env::temp_dir().join(format!("single-rotation-target-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    assert_eq!(load_target(&directory).unwrap(), None);
+    save_target(&directory, Some(123_456)).unwrap();
+    assert_eq!(load_target(&directory).unwrap(), Some(123_456));
+    save_target(&directory, None).unwrap();
+    assert_eq!(load_target(&directory).unwrap(), None);
+    fs::remove_dir_all(&directory).unwrap();
+}
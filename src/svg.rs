@@ -0,0 +1,175 @@
+use std::io::{self, Write};
+use super::display::{COLORS, RADIUS, SCALE};
+use super::regions::RegionTree;
+
+/// Parameters of an SVG export
+#[derive(Debug, Clone)]
+pub struct SvgConfig {
+    /// Distance between two cells, in SVG units
+    pub scale: f32,
+    /// Radius of the particles, in SVG units
+    pub radius: f32,
+    /// Draw the squares of the Margolus partition of the current phase
+    pub grid: bool,
+    /// Draw the path of each particle over that many generations, starting from the current one
+    pub trajectories: usize,
+    /// Space around the pattern, in cells
+    pub margin: i64,
+}
+
+impl Default for SvgConfig {
+    fn default() -> Self {
+        Self {
+            scale: SCALE,
+            radius: RADIUS,
+            grid: false,
+            trajectories: 0,
+            margin: 2,
+        }
+    }
+}
+
+/// Returns the positions of each particle of `tree`, indexed by id, over the next `generations` generations, starting with the current one.
+/// The first list is empty, like the first slot of `RegionTree::cells`
+pub fn trajectories(tree: &RegionTree, generations: usize) -> Vec<Vec<(i64, i64)>> {
+    let mut tree = tree.clone();
+    let mut res = vec![Vec::new(); tree.cells.len()];
+    for generation in 0..=generations {
+        if generation > 0 {
+            tree.tick();
+        }
+        for (id, position, _) in tree.particles() {
+            res[id].push(position);
+        }
+    }
+    res
+}
+
+/// Writes `tree` as an SVG image on a black background, with its particles colored with `COLORS`, at their current position.
+/// Paths cross the edges of a torus as separate polylines
+pub fn write_svg<W: Write>(tree: &RegionTree, config: &SvgConfig, writer: &mut W) -> io::Result<()> {
+    let paths = if config.trajectories > 0 {
+        trajectories(tree, config.trajectories)
+    } else {
+        vec![]
+    };
+
+    // Bounds of the image, in cells
+    let points = tree.particles().map(|(_, position, _)| position).chain(paths.iter().flatten().copied());
+    let (mut left, mut top, mut right, mut bottom) = (i64::MAX, i64::MAX, i64::MIN, i64::MIN);
+    for (x, y) in points {
+        left = left.min(x);
+        top = top.min(y);
+        right = right.max(x);
+        bottom = bottom.max(y);
+    }
+    if let Some((width, height)) = tree.topology().size() {
+        left = left.min(0);
+        top = top.min(0);
+        right = right.max(width - 1);
+        bottom = bottom.max(height - 1);
+    }
+    if left > right {
+        left = 0;
+        top = 0;
+        right = 0;
+        bottom = 0;
+    }
+    let (left, top, right, bottom) = (left - config.margin, top - config.margin, right + config.margin, bottom + config.margin);
+
+    let scale = config.scale;
+    let px = |x: i64| x as f32 * scale;
+    let color = |i: usize| {
+        let (_, r, g, b) = COLORS[i % COLORS.len()];
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    };
+    let (x0, y0) = (px(left) - scale / 2.0, px(top) - scale / 2.0);
+    let (width, height) = ((right - left + 1) as f32 * scale, (bottom - top + 1) as f32 * scale);
+
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        x0, y0, width, height, width, height
+    )?;
+    writeln!(writer, r#"<rect x="{}" y="{}" width="{}" height="{}" fill="black"/>"#, x0, y0, width, height)?;
+
+    if config.grid {
+        // Squares of the partition start at even coordinates on even generations, and at odd coordinates on odd ones
+        let phase = (tree.step % 2) as i64;
+        writeln!(writer, r##"<g stroke="#404040" stroke-width="{}">"##, scale / 16.0)?;
+        for x in (left..=right + 1).filter(|x| (x - phase).rem_euclid(2) == 0) {
+            writeln!(writer, r#"<line x1="{}" y1="{}" x2="{}" y2="{}"/>"#, px(x) - scale / 2.0, y0, px(x) - scale / 2.0, y0 + height)?;
+        }
+        for y in (top..=bottom + 1).filter(|y| (y - phase).rem_euclid(2) == 0) {
+            writeln!(writer, r#"<line x1="{}" y1="{}" x2="{}" y2="{}"/>"#, x0, px(y) - scale / 2.0, x0 + width, px(y) - scale / 2.0)?;
+        }
+        writeln!(writer, "</g>")?;
+    }
+
+    // Edges of bounded universes
+    if let Some((w, h)) = tree.topology().size() {
+        writeln!(
+            writer,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{}" stroke-opacity="0.5"/>"#,
+            -scale / 2.0, -scale / 2.0, px(w), px(h), color(0)
+        )?;
+    }
+
+    if !paths.is_empty() {
+        writeln!(writer, r#"<g fill="none" stroke-width="{}" stroke-opacity="0.6" stroke-linejoin="round">"#, scale / 8.0)?;
+        for (id, path) in paths.iter().enumerate().filter(|(_, path)| path.len() > 1) {
+            // Particles move by at most one cell per generation, so longer jumps wrap around a torus
+            let mut start = 0;
+            for end in 1..=path.len() {
+                let wraps = end < path.len()
+                    && ((path[end].0 - path[end - 1].0).abs() > 1 || (path[end].1 - path[end - 1].1).abs() > 1);
+                if end == path.len() || wraps {
+                    if end - start > 1 {
+                        let points = path[start..end]
+                            .iter()
+                            .map(|&(x, y)| format!("{},{}", px(x), px(y)))
+                            .collect::<Vec<_>>();
+                        writeln!(writer, r#"<polyline stroke="{}" points="{}"/>"#, color(tree.colors[id]), points.join(" "))?;
+                    }
+                    start = end;
+                }
+            }
+        }
+        writeln!(writer, "</g>")?;
+    }
+
+    for (_, (x, y), c) in tree.particles() {
+        writeln!(writer, r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#, px(x), px(y), config.radius, color(c))?;
+    }
+    writeln!(writer, "</svg>")
+}
+
+#[test]
+fn test_svg() {
+    let mut tree = RegionTree::new();
+    tree.insert(0, 0, 1);
+    tree.insert(0, 1, 0);
+    tree.insert(0, 3, 0);
+    tree.insert(0, 4, 0);
+
+    let paths = trajectories(&tree, 12);
+    assert!(paths[0].is_empty());
+    assert_eq!(paths[1].len(), 13);
+    assert_eq!(paths[1][0], (0, 0));
+    assert!(paths[1].windows(2).all(|w| (w[0].0 - w[1].0).abs() <= 1 && (w[0].1 - w[1].1).abs() <= 1));
+
+    let config = SvgConfig {
+        grid: true,
+        trajectories: 12,
+        ..SvgConfig::default()
+    };
+    let mut svg = Vec::new();
+    write_svg(&tree, &config, &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.trim_end().ends_with("</svg>"));
+    assert_eq!(svg.matches("<circle").count(), 4);
+    assert_eq!(svg.matches("<polyline").count(), 4);
+    assert!(svg.contains(r##"fill="#194350""##));
+    assert!(svg.contains("<line"));
+}
@@ -0,0 +1,394 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window};
+use super::capture::get_island;
+use super::display::{self, Camera, Trails, COLORS};
+use super::lanczos::LanczosInterpolator;
+use super::regions::RegionTree;
+use super::rle::{RleMode, RleSettings, RleWriter};
+
+/// Order of the Lanczos filter
+const LANCZOS_ORDER: usize = 3;
+/// Minimum time between two frames
+const FRAME_TIME: Duration = Duration::from_millis(20);
+/// Distance the arrow keys move the view by on each frame, in pixels
+const PAN_SPEED: f32 = 12.0;
+/// Factor the view is zoomed in or out by with each notch of the wheel, or each press of +/-
+const ZOOM_STEP: f32 = 1.25;
+/// Factor Shift+[ and Shift+] change the time between two states by
+const INTERVAL_STEP: f32 = 1.5;
+/// Fraction of the distance to the tracked particles that the view moves by on each frame
+const FOLLOW_SPEED: f32 = 0.15;
+/// Bounds of the time between two states, in milliseconds
+const MIN_INTERVAL: u32 = 5;
+const MAX_INTERVAL: u32 = 10_000;
+
+/// What the view follows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tracking {
+    /// The particles with these ids, picked as an island of regions with `get_island`
+    Object(Vec<usize>),
+    /// Every particle of the universe
+    Universe,
+}
+
+/// The interactive window: the simulation, the position of the view, the playback state, the editor and the state of the mouse
+pub struct Viewer {
+    window: Window,
+    interpolator: LanczosInterpolator,
+    /// Part of the universe that is shown
+    pub camera: Camera,
+    /// Positions of the particles on the last frame
+    positions: Vec<(f32, f32)>,
+    /// Position of the mouse on the previous frame, while the view is being dragged
+    drag: Option<(f32, f32)>,
+    /// Whether the simulation is paused
+    pub paused: bool,
+    /// Whether to show the next state on the next frame while paused
+    single_step: bool,
+
+    /// Color of the particles added with the mouse, as an index in `COLORS`
+    pub color: usize,
+    /// Pattern placed under the mouse with V
+    pub stamp: Option<RegionTree>,
+    /// File S saves the universe into; defaults to `universe-GENERATION.rle`
+    pub output: Option<PathBuf>,
+    /// While the left button is held: whether particles are being added or removed, and the last cell that was edited
+    stroke: Option<(bool, (i64, i64))>,
+
+    /// The center of mass of these particles is kept at the center of the view, until the view is moved by hand
+    pub tracking: Option<Tracking>,
+    /// Whether the middle button was held on the previous frame
+    middle: bool,
+
+    /// Paths of the particles over the last frames, drawn while `show_trails` is set
+    pub trails: Trails,
+    /// Whether the trails are drawn
+    pub show_trails: bool,
+}
+
+impl Viewer {
+    /// Opens a window showing `tree`, animated following `settings`
+    pub fn new(tree: RegionTree, settings: RleSettings) -> Self {
+        let fps = (1000 / settings.interval.max(1)).max(1);
+        let timesteps = (50 / fps as usize).max(1);
+        let mut interpolator = LanczosInterpolator::new(tree, LANCZOS_ORDER, timesteps, settings.smoothing, settings.interval, settings.steps);
+        // Start from the generation of the pattern
+        interpolator.set_speed(settings.steps, false);
+        Self {
+            window: display::spawn(),
+            interpolator,
+            camera: Camera::default(),
+            positions: Vec::new(),
+            drag: None,
+            paused: false,
+            single_step: false,
+
+            color: 0,
+            stamp: None,
+            output: None,
+            stroke: None,
+
+            tracking: None,
+            middle: false,
+
+            trails: Trails::default(),
+            show_trails: false,
+        }
+    }
+
+    /// Returns the simulation being shown, which is ahead of what is displayed
+    pub fn tree(&self) -> &RegionTree {
+        self.interpolator.tree()
+    }
+
+    /// Runs the window until it is closed or Escape is pressed
+    pub fn run(&mut self) {
+        println!();
+        let mut previous_time = Instant::now();
+        while self.window.is_open() && !self.window.is_key_down(Key::Escape) {
+            let start = Instant::now();
+            let elapsed = previous_time.elapsed();
+            previous_time = start;
+
+            self.handle_input();
+            let dt = if !self.paused {
+                elapsed
+            } else if self.single_step {
+                self.single_step = false;
+                Duration::from_millis(self.interpolator.interval as u64)
+            } else {
+                Duration::from_millis(0)
+            };
+            self.positions = self.interpolator.get(dt);
+            if self.show_trails && (dt > Duration::from_millis(0) || self.trails.is_empty()) {
+                self.trails.push(&self.positions);
+            }
+            self.follow();
+            let trails = if self.show_trails { Some(&self.trails) } else { None };
+            display::draw(&mut self.window, self.interpolator.tree(), &self.positions, &self.camera, trails);
+
+            print!("\x1b[1F\x1b[0K");
+            println!("{}", self.status());
+            if let Some(duration) = FRAME_TIME.checked_sub(start.elapsed()) {
+                std::thread::sleep(duration);
+            }
+        }
+    }
+
+    /// Returns the line printed below the window: the generation and the playback state
+    fn status(&self) -> String {
+        let mut res = format!(
+            "Step: {}, {} generations every {} ms",
+            self.interpolator.displayed_step(),
+            self.interpolator.step_delta(),
+            self.interpolator.interval
+        );
+        if self.interpolator.is_reversed() {
+            res.push_str(", reversed");
+        }
+        if self.paused {
+            res.push_str(&format!(", paused, editing with color {}", self.color));
+        }
+        match &self.tracking {
+            Some(Tracking::Object(ids)) => res.push_str(&format!(", following {} particles", ids.len())),
+            Some(Tracking::Universe) => res.push_str(", following the universe"),
+            None => {}
+        }
+        res
+    }
+
+    /// Pans with the mouse and the arrow keys, zooms with the wheel and +/-, frames the pattern with F and goes back to the origin with Home.
+    /// Space pauses, `.` shows the next state, [ and ] change the number of generations between states, Shift+[ and Shift+] the time between them,
+    /// and R reverses the simulation.
+    /// While paused, the left button adds or removes particles, the number keys pick their color and V stamps the pattern of `stamp`;
+    /// S saves the universe at any time.
+    /// The middle button or T follows the object under the mouse, and U follows the whole universe; moving the view by hand stops following.
+    /// L shows or hides the trails of the particles
+    fn handle_input(&mut self) {
+        let size = self.window.get_size();
+        let center = (size.0 as f32 / 2.0, size.1 as f32 / 2.0);
+
+        let mouse = self.window.get_mouse_pos(MouseMode::Pass);
+        let left = self.window.get_mouse_down(MouseButton::Left);
+        let right = self.window.get_mouse_down(MouseButton::Right);
+        if self.paused && left {
+            self.drag = None;
+            if let Some(mouse) = mouse {
+                self.paint(self.cell_at(mouse));
+            }
+        } else {
+            self.stroke = None;
+            if left || right {
+                if let (Some((x, y)), Some((mx, my))) = (self.drag, mouse) {
+                    if (mx, my) != (x, y) {
+                        self.camera.pan(mx - x, my - y);
+                        self.tracking = None;
+                    }
+                }
+                self.drag = mouse;
+            } else {
+                self.drag = None;
+            }
+        }
+
+        let middle = self.window.get_mouse_down(MouseButton::Middle);
+        if middle && !self.middle {
+            if let Some(mouse) = mouse {
+                self.track_object(self.cell_at(mouse));
+            }
+        }
+        self.middle = middle;
+
+        if let Some((_, scroll)) = self.window.get_scroll_wheel() {
+            if scroll != 0.0 {
+                let factor = if scroll > 0.0 { ZOOM_STEP } else { 1.0 / ZOOM_STEP };
+                self.camera.zoom(factor, mouse.unwrap_or(center), size);
+            }
+        }
+
+        let (mut dx, mut dy) = (0.0, 0.0);
+        if self.window.is_key_down(Key::Left) {
+            dx += PAN_SPEED;
+        }
+        if self.window.is_key_down(Key::Right) {
+            dx -= PAN_SPEED;
+        }
+        if self.window.is_key_down(Key::Up) {
+            dy += PAN_SPEED;
+        }
+        if self.window.is_key_down(Key::Down) {
+            dy -= PAN_SPEED;
+        }
+        if dx != 0.0 || dy != 0.0 {
+            self.camera.pan(dx, dy);
+            self.tracking = None;
+        }
+
+        let shift = self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
+        let (step_delta, reverse) = (self.interpolator.step_delta(), self.interpolator.is_reversed());
+        for key in self.window.get_keys_pressed(KeyRepeat::Yes).unwrap_or_default() {
+            match key {
+                Key::Equal | Key::NumPadPlus => self.camera.zoom(ZOOM_STEP, center, size),
+                Key::Minus | Key::NumPadMinus => self.camera.zoom(1.0 / ZOOM_STEP, center, size),
+                Key::F => {
+                    self.tracking = None;
+                    self.fit();
+                }
+                Key::Home => {
+                    self.tracking = None;
+                    self.camera = Camera::default();
+                }
+                Key::T => {
+                    if let Some(mouse) = mouse {
+                        self.track_object(self.cell_at(mouse));
+                    }
+                }
+                Key::U if self.tracking == Some(Tracking::Universe) => self.tracking = None,
+                Key::U => self.tracking = Some(Tracking::Universe),
+                Key::Space => self.paused = !self.paused,
+                Key::Period => {
+                    self.paused = true;
+                    self.single_step = true;
+                }
+                Key::LeftBracket if shift => self.scale_interval(INTERVAL_STEP),
+                Key::RightBracket if shift => self.scale_interval(1.0 / INTERVAL_STEP),
+                Key::LeftBracket if step_delta > 1 => self.interpolator.set_speed(step_delta / 2, reverse),
+                Key::RightBracket => self.interpolator.set_speed(step_delta * 2, reverse),
+                Key::R => self.interpolator.set_speed(step_delta, !reverse),
+                Key::S => self.save(),
+                Key::L => {
+                    self.show_trails = !self.show_trails;
+                    self.trails.clear();
+                }
+                Key::V if self.paused => {
+                    if let Some(mouse) = mouse {
+                        self.place_stamp(self.cell_at(mouse));
+                    }
+                }
+                _ => {
+                    let digits = [Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9];
+                    if let Some(color) = digits.iter().position(|&digit| digit == key) {
+                        if color < COLORS.len() {
+                            self.color = color;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Follows the particles of the island of regions containing `cell`, or stops following if there are none
+    fn track_object(&mut self, (x, y): (i64, i64)) {
+        let tree = self.interpolator.displayed_tree();
+        let ids = get_island(&tree, x, y, 0)
+            .into_iter()
+            .filter_map(|(x, y)| tree.region_at(x, y))
+            .flat_map(|region| region.cells().iter().flatten().copied().filter(|&id| id > 0).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        self.tracking = if ids.is_empty() { None } else { Some(Tracking::Object(ids)) };
+    }
+
+    /// Moves the view towards the center of mass of the tracked particles, as they are displayed
+    fn follow(&mut self) {
+        let tree = self.interpolator.tree();
+        let positions = &self.positions;
+        let alive = |id: &usize| *id >= 1 && *id <= positions.len() && tree.is_alive(*id);
+        let ids: Box<dyn Iterator<Item = usize>> = match &self.tracking {
+            Some(Tracking::Object(ids)) => Box::new(ids.iter().copied().filter(alive)),
+            Some(Tracking::Universe) => Box::new((1..=positions.len()).filter(alive)),
+            None => return,
+        };
+
+        let (mut x, mut y, mut n) = (0.0, 0.0, 0);
+        for id in ids {
+            x += positions[id - 1].0;
+            y += positions[id - 1].1;
+            n += 1;
+        }
+        if n == 0 {
+            self.tracking = None;
+            return
+        }
+        self.camera.x += (x / n as f32 - self.camera.x) * FOLLOW_SPEED;
+        self.camera.y += (y / n as f32 - self.camera.y) * FOLLOW_SPEED;
+    }
+
+    /// Returns the cell under the pixel `mouse`
+    fn cell_at(&self, mouse: (f32, f32)) -> (i64, i64) {
+        let (x, y) = self.camera.to_world(mouse, self.window.get_size());
+        (x.round() as i64, y.round() as i64)
+    }
+
+    /// Adds a particle at `cell` if the stroke started on an empty cell, or removes it otherwise
+    fn paint(&mut self, (x, y): (i64, i64)) {
+        if let Some((_, last)) = self.stroke {
+            if last == (x, y) {
+                return
+            }
+        }
+        let color = self.color;
+        let mut adding = self.stroke.map(|(adding, _)| adding);
+        self.interpolator.edit(|tree| {
+            if *adding.get_or_insert(tree.get(x, y) == 0) {
+                tree.insert(x, y, color);
+            } else {
+                tree.remove(x, y);
+            }
+        });
+        self.stroke = adding.map(|adding| (adding, (x, y)));
+    }
+
+    /// Adds the particles of `stamp`, with its top-left corner at `cell`.
+    /// The pattern is moved by an even offset, so that it evolves the same way, or by an odd one if the phases differ and the rule allows it
+    fn place_stamp(&mut self, (x, y): (i64, i64)) {
+        let stamp = match &self.stamp {
+            Some(stamp) => stamp,
+            None => return,
+        };
+        let left = stamp.particles().map(|(_, (x, _), _)| x).min().unwrap_or(0);
+        let top = stamp.particles().map(|(_, (_, y), _)| y).min().unwrap_or(0);
+        let (mut dx, mut dy) = (x - left, y - top);
+        dx -= dx.rem_euclid(2);
+        dy -= dy.rem_euclid(2);
+        if self.interpolator.displayed_step() % 2 != stamp.step % 2 && self.interpolator.tree().rule.is_phase_symmetric() {
+            dx += 1;
+            dy += 1;
+        }
+
+        self.interpolator.edit(|tree| {
+            for (_, (x, y), color) in stamp.particles() {
+                tree.insert(x + dx, y + dy, color);
+            }
+        });
+    }
+
+    /// Writes the displayed generation of the universe as RLE into `output`
+    fn save(&mut self) {
+        let tree = self.interpolator.displayed_tree();
+        let path = self.output.clone().unwrap_or_else(|| PathBuf::from(format!("universe-{}.rle", tree.step)));
+        let mut writer = RleWriter::new(RleMode::Legacy);
+        writer.settings = RleSettings {
+            steps: self.interpolator.step_delta(),
+            interval: self.interpolator.interval,
+            smoothing: self.interpolator.smoothing(),
+        };
+        match std::fs::write(&path, writer.to_rle(&tree)) {
+            Ok(()) => println!("Saved generation {} into {}\n", tree.step, path.display()),
+            Err(err) => println!("Couldn't write {}: {}\n", path.display(), err),
+        }
+    }
+
+    /// Multiplies the time between two states by `factor`
+    fn scale_interval(&mut self, factor: f32) {
+        let interval = (self.interpolator.interval as f32 * factor).round() as u32;
+        self.interpolator.interval = interval.clamp(MIN_INTERVAL, MAX_INTERVAL);
+    }
+
+    /// Frames the bounding box of the living particles
+    fn fit(&mut self) {
+        if let Some((top_left, bottom_right)) = display::bounds(self.interpolator.tree(), &self.positions) {
+            self.camera.fit(top_left, bottom_right, self.window.get_size());
+        }
+    }
+}